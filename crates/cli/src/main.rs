@@ -1,7 +1,9 @@
 use clap::Parser;
 use serde_json::to_string_pretty;
 use std::fs;
+use std::time::Duration;
 
+use runtime::errors::RuntimeError;
 use runtime::exec::ExecutionRuntime;
 
 #[derive(Parser, Debug)]
@@ -9,14 +11,93 @@ use runtime::exec::ExecutionRuntime;
 #[command(about = "Run .apisql queries against JSON APIs")]
 struct Args {
     file: String,
+
+    /// Skip this many rows, overriding OFFSET in the file
+    #[arg(long)]
+    offset: Option<u32>,
+
+    /// Return at most this many rows, overriding LIMIT in the file
+    #[arg(long)]
+    limit: Option<u32>,
+
+    /// How many FOR EACH item requests to send at once
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// Give up connecting to a host after this many seconds [default: 10]
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+
+    /// Give up on a request after this many seconds, unless it sets its own TIMEOUT [default: 30]
+    #[arg(long)]
+    read_timeout: Option<u64>,
+
+    /// Give up on the whole program after this many seconds
+    #[arg(long)]
+    deadline: Option<u64>,
+
+    /// Log every HTTP attempt, including retries, to stderr
+    #[arg(short, long)]
+    verbose: bool,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let source = fs::read_to_string(&args.file)?;
-    let mut executor = ExecutionRuntime::new();
-    let result = executor.run_source(&source)?;
+    let mut executor = ExecutionRuntime::new().with_verbose(args.verbose);
+    if let Some(offset) = args.offset {
+        executor = executor.with_offset(offset);
+    }
+    if let Some(limit) = args.limit {
+        executor = executor.with_limit(limit);
+    }
+    if let Some(concurrency) = args.concurrency {
+        executor = executor.with_concurrency(concurrency);
+    }
+    if let Some(secs) = args.connect_timeout {
+        executor = executor.with_connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.read_timeout {
+        executor = executor.with_read_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = args.deadline {
+        executor = executor.with_deadline(Duration::from_secs(secs));
+    }
+    let result = match executor.run_source(&source) {
+        Ok(result) => result,
+        Err(RuntimeError::Parse(e)) => {
+            eprintln!("error: {}", e);
+            if let Some(span) = e.span() {
+                eprintln!("{}", render_span(&args.file, &source, span.start, span.end));
+            }
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     println!("{}", to_string_pretty(&result)?);
     Ok(())
 }
+
+// Prints the offending source line with a caret under the span
+fn render_span(file: &str, source: &str, start: usize, end: usize) -> String {
+    let line_start = source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = source[start..]
+        .find('\n')
+        .map(|i| start + i)
+        .unwrap_or(source.len());
+    let line_no = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let width = source[start..end.clamp(start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    format!(
+        "{gutter}--> {file}:{line_no}:{column}\n{gutter} |\n{line_no} | {}\n{gutter} | {}{}",
+        &source[line_start..line_end],
+        " ".repeat(column - 1),
+        "^".repeat(width),
+    )
+}
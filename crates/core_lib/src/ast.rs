@@ -1,21 +1,128 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
+// == Source Spans ==
+// Byte offsets into the source plus the 1-based line/column of `start`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // Covers everything from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub using_block: Option<UsingBlock>,
     pub request_blocks: Vec<RequestBlock>,
     pub response_blocks: Vec<ResponseBlock>,
 }
 
+impl Program {
+    // The request a response reads from: the one it names, otherwise the
+    // closest REQUEST above it (or the first one when there is none above).
+    pub fn request_for(&self, response: &ResponseBlock) -> Option<&RequestBlock> {
+        match &response.request {
+            Some(request) => self.request_blocks.iter().find(|r| r.name == request.name),
+            None => self
+                .request_blocks
+                .iter()
+                .rev()
+                .find(|r| r.span.start < response.span.start)
+                .or(self.request_blocks.first()),
+        }
+    }
+
+    // Requests ordered so that each one comes after everything it depends on,
+    // otherwise in declaration order. A cycle is returned as the chain of
+    // request names that closes it, e.g. `["A", "B", "A"]`.
+    pub fn request_order(&self) -> Result<Vec<&RequestBlock>, Vec<String>> {
+        // 0 = unvisited, 1 = on the current path, 2 = done
+        let mut state = vec![0u8; self.request_blocks.len()];
+        let mut path: Vec<usize> = Vec::new();
+        let mut order = Vec::new();
+        for index in 0..self.request_blocks.len() {
+            self.visit_request(index, &mut state, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    // The request named `name` after everything it depends on, in the same
+    // order as request_order. Unknown names give an empty order.
+    pub fn request_order_for(&self, name: &str) -> Result<Vec<&RequestBlock>, Vec<String>> {
+        let mut state = vec![0u8; self.request_blocks.len()];
+        let mut order = Vec::new();
+        if let Some(index) = self.request_blocks.iter().position(|r| r.name == name) {
+            self.visit_request(index, &mut state, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit_request<'a>(
+        &'a self,
+        index: usize,
+        state: &mut [u8],
+        path: &mut Vec<usize>,
+        order: &mut Vec<&'a RequestBlock>,
+    ) -> Result<(), Vec<String>> {
+        match state[index] {
+            2 => return Ok(()),
+            1 => {
+                let from = path.iter().position(|&i| i == index).unwrap_or(0);
+                return Err(path[from..]
+                    .iter()
+                    .chain([&index])
+                    .map(|&i| self.request_blocks[i].name.clone())
+                    .collect());
+            }
+            _ => {}
+        }
+
+        state[index] = 1;
+        path.push(index);
+        let request = &self.request_blocks[index];
+        for name in &request.depends_on {
+            if let Some(dep) = self.request_blocks.iter().position(|r| &r.name == name) {
+                self.visit_request(dep, state, path, order)?;
+            }
+        }
+        path.pop();
+        state[index] = 2;
+        order.push(request);
+        Ok(())
+    }
+}
+
 // == Variables Block ==
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UsingBlock {
     pub var_declarations: Vec<VarDeclaration>,
+    pub span: Span,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VarDeclaration {
     pub name: String,
     pub value: String,
+    pub span: Span,
 }
 
 // == Request Block ==
@@ -32,6 +139,7 @@ pub enum HttpMethods {
 pub struct Header {
     pub key: String,
     pub value: String,
+    pub span: Span,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -46,41 +154,422 @@ pub struct RequestBlock {
     pub method: HttpMethods,
     pub url: String,
     pub headers: Vec<Header>,
+    // QUERY section, appended to the URL when the request is sent
+    pub query_params: Vec<Param>,
+    pub body: Option<RequestBody>,
     pub cache: CacheDuration,
+    pub for_each: Option<ForEach>,
+    pub paginate: Option<Paginate>,
+    // EXPECT STATUS; empty means any 2xx
+    pub expect_status: Vec<StatusRange>,
+    pub on_status: Vec<OnStatus>,
+    pub retry: Option<Retry>,
+    // TIMEOUT 5s; limits each attempt from connecting to the end of the body
+    pub timeout_ms: Option<u64>,
+    pub auth: Option<Auth>,
+    pub sign: Option<Sign>,
+    // Requests whose output this one reads through `{Name.body.path}` placeholders
+    pub depends_on: Vec<String>,
+    pub span: Span,
+}
+
+// `BODY JSON { ... }`, `BODY FORM` with one `key: value` per line, or `BODY RAW text`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RequestBody {
+    Json(String),
+    Form(Vec<Param>),
+    Raw(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Param {
+    pub key: String,
+    pub value: String,
+    pub span: Span,
+}
+
+// `404` or `200..299`, both ends inclusive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl StatusRange {
+    pub fn contains(&self, status: u16) -> bool {
+        (self.start..=self.end).contains(&status)
+    }
+}
+
+// `ON STATUS 404 RETURN []` answers those statuses with `value` as the body
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OnStatus {
+    pub statuses: Vec<StatusRange>,
+    pub value: serde_json::Value,
+    pub span: Span,
+}
+
+// `RETRY 3 [BACKOFF 500] [ON 429, 503]` resends the request up to 3 more times
+// after a connection error or one of the listed statuses
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Retry {
+    pub retries: u32,
+    pub backoff_ms: u64,
+    pub statuses: Vec<StatusRange>,
+    pub span: Span,
+}
+
+// `AUTH <scheme> ...` on one line; every value may hold placeholders
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Auth {
+    pub scheme: AuthScheme,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AuthScheme {
+    // BASIC user pass
+    Basic {
+        user: String,
+        password: String,
+    },
+    // BEARER token
+    Bearer {
+        token: String,
+    },
+    // APIKEY header|query name value
+    ApiKey {
+        location: ApiKeyLocation,
+        name: String,
+        value: String,
+    },
+    // OAUTH2 CLIENT_CREDENTIALS token_url client_id secret [scope]; the runtime
+    // fetches the access token and reuses it until it expires
+    OAuth2 {
+        token_url: String,
+        client_id: String,
+        secret: String,
+        scope: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyLocation {
+    Header,
+    Query,
+}
+
+// `SIGN <scheme> ...` on one line, applied to each attempt just before sending
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sign {
+    pub scheme: SignScheme,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SignScheme {
+    // HMAC-SHA256 secret [HEADER name] [TIMESTAMP name] [OVER "template"]: the
+    // hex digest of the template, filled in with {method}, {url}, {path},
+    // {query}, {body}, {body_sha256} and {timestamp}, is sent in `header`. A
+    // template that uses {timestamp} also sends it in `timestamp_header`.
+    HmacSha256 {
+        secret: String,
+        header: String,
+        timestamp_header: String,
+        canonical: String,
+    },
+    // AWS_SIGV4 service region [KEY id SECRET key [TOKEN session]]; without KEY
+    // the credentials come from the AWS_* environment variables
+    AwsSigV4 {
+        service: String,
+        region: String,
+        access_key: Option<String>,
+        secret_key: Option<String>,
+        session_token: Option<String>,
+    },
+}
+
+// How a value substituted for a placeholder is escaped for the text it lands in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    None,
+    Json,
+    // Percent-encoded in the path or query of a URL unless written as `{+var}`
+    Url,
+}
+
+impl RequestBlock {
+    // Every text that may hold placeholders: the URL, header and query values,
+    // the AUTH values and the body
+    pub fn texts(&self) -> Vec<&str> {
+        let mut texts = vec![self.url.as_str()];
+        texts.extend(self.headers.iter().map(|h| h.value.as_str()));
+        texts.extend(self.query_params.iter().map(|p| p.value.as_str()));
+        if let Some(auth) = &self.auth {
+            match &auth.scheme {
+                AuthScheme::Basic { user, password } => texts.extend([user.as_str(), password]),
+                AuthScheme::Bearer { token } => texts.push(token),
+                AuthScheme::ApiKey { value, .. } => texts.push(value),
+                AuthScheme::OAuth2 {
+                    token_url,
+                    client_id,
+                    secret,
+                    scope,
+                } => {
+                    texts.extend([token_url.as_str(), client_id, secret]);
+                    texts.extend(scope.as_deref());
+                }
+            }
+        }
+        if let Some(sign) = &self.sign {
+            match &sign.scheme {
+                SignScheme::HmacSha256 { secret, .. } => texts.push(secret),
+                SignScheme::AwsSigV4 {
+                    service,
+                    region,
+                    access_key,
+                    secret_key,
+                    session_token,
+                } => {
+                    texts.extend([service.as_str(), region]);
+                    texts.extend(access_key.as_deref());
+                    texts.extend(secret_key.as_deref());
+                    texts.extend(session_token.as_deref());
+                }
+            }
+        }
+        match &self.body {
+            Some(RequestBody::Json(text) | RequestBody::Raw(text)) => texts.push(text),
+            Some(RequestBody::Form(params)) => {
+                texts.extend(params.iter().map(|p| p.value.as_str()))
+            }
+            None => {}
+        }
+        texts
+    }
+
+    // Rewrites each of `texts` in place
+    pub fn map_texts<E>(
+        &mut self,
+        mut f: impl FnMut(&str, Escape) -> Result<String, E>,
+    ) -> Result<(), E> {
+        self.url = f(&self.url, Escape::Url)?;
+        for header in self.headers.iter_mut() {
+            header.value = f(&header.value, Escape::None)?;
+        }
+        // Encoded along with their keys by `full_url`
+        for param in self.query_params.iter_mut() {
+            param.value = f(&param.value, Escape::None)?;
+        }
+        if let Some(auth) = &mut self.auth {
+            match &mut auth.scheme {
+                AuthScheme::Basic { user, password } => {
+                    *user = f(user, Escape::None)?;
+                    *password = f(password, Escape::None)?;
+                }
+                AuthScheme::Bearer { token } => *token = f(token, Escape::None)?,
+                AuthScheme::ApiKey { value, .. } => *value = f(value, Escape::None)?,
+                AuthScheme::OAuth2 {
+                    token_url,
+                    client_id,
+                    secret,
+                    scope,
+                } => {
+                    *token_url = f(token_url, Escape::Url)?;
+                    *client_id = f(client_id, Escape::None)?;
+                    *secret = f(secret, Escape::None)?;
+                    if let Some(scope) = scope {
+                        *scope = f(scope, Escape::None)?;
+                    }
+                }
+            }
+        }
+        // The HMAC template is filled in when signing, not here
+        if let Some(sign) = &mut self.sign {
+            match &mut sign.scheme {
+                SignScheme::HmacSha256 { secret, .. } => *secret = f(secret, Escape::None)?,
+                SignScheme::AwsSigV4 {
+                    service,
+                    region,
+                    access_key,
+                    secret_key,
+                    session_token,
+                } => {
+                    *service = f(service, Escape::None)?;
+                    *region = f(region, Escape::None)?;
+                    for text in [access_key, secret_key, session_token]
+                        .into_iter()
+                        .flatten()
+                    {
+                        *text = f(text, Escape::None)?;
+                    }
+                }
+            }
+        }
+        match &mut self.body {
+            Some(RequestBody::Json(text)) => *text = f(text, Escape::Json)?,
+            Some(RequestBody::Raw(text)) => *text = f(text, Escape::None)?,
+            Some(RequestBody::Form(params)) => {
+                for param in params.iter_mut() {
+                    param.value = f(&param.value, Escape::None)?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+// `FOR EACH p IN GetPokemon.body.results` sends the request once per item,
+// with `{p}` or `{p.url}` filled in, and collects the bodies into one array
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForEach {
+    pub alias: String,
+    pub request: String,
+    pub from_type: FromType,
+    pub path: Vec<PathSegment>,
+    pub span: Span,
+}
+
+// `PAGINATE <strategy> [MAX PAGES n] [MAX ITEMS n]`; at least one guard is required
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Paginate {
+    pub strategy: PageStrategy,
+    pub max_pages: Option<u32>,
+    pub max_items: Option<u32>,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PageStrategy {
+    // PAGE page [START 1]: the page number, counting up from START
+    Page {
+        param: String,
+        start: u32,
+    },
+    // OFFSET offset: the number of items fetched so far
+    Offset {
+        param: String,
+    },
+    // CURSOR cursor FROM body.next: a value taken from the previous page
+    Cursor {
+        param: String,
+        path: Vec<PathSegment>,
+    },
+    // LINK: the `rel="next"` URL of the Link header
+    Link,
 }
 
 // == Response Block ==
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ResponseBlock {
+    // `RESPONSE GetUsers` or `RESPONSE FROM GetUsers`
+    pub request: Option<RequestRef>,
     pub query: QueryBlock,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequestRef {
+    pub name: String,
+    pub span: Span,
 }
 
-// TODO: Add Order By, Group By, Joins etc
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct QueryBlock {
     pub select_clause: SelectClause,
+    pub distinct: Option<Distinct>,
     pub from_clause: FromClause,
     pub where_clause: Option<Expression>,
+    pub group_by: Vec<Expression>,
+    pub having: Option<Expression>,
+    pub order_by: Vec<OrderByItem>,
     pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub span: Span,
+}
+
+impl QueryBlock {
+    // Other requests read through `FROM Name.body` or JOIN
+    pub fn referenced_requests(&self) -> Vec<&str> {
+        let from = &self.from_clause;
+        let mut names: Vec<&str> = Vec::new();
+        for name in from
+            .request
+            .iter()
+            .chain(from.joins.iter().filter_map(|j| j.request.as_ref()))
+        {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SelectClause {
-    Fields(Vec<String>),
+    Fields(Vec<FieldPath>),
     Objects(Vec<SelectField>),
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Distinct {
+    // SELECT DISTINCT compares whole projected rows
+    Rows,
+    // SELECT DISTINCT ON (expr, ...) keeps the first row per key after ORDER BY
+    On(Vec<Expression>),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SelectField {
     pub alias: String,
     pub expression: Option<Expression>,
+    pub span: Span,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FromClause {
+    // `FROM GetOrders.body.data` reads another request; None reads the block's own
+    pub request: Option<String>,
+    pub from_type: FromType,
+    pub path: Vec<PathSegment>,
+    // `FROM body.orders AS order` also exposes each row as `order`
+    pub alias: Option<String>,
+    pub joins: Vec<Join>,
+    pub unnest: Vec<Unnest>,
+    pub span: Span,
+}
+
+// `[INNER | LEFT [OUTER]] JOIN GetUsers.body AS u ON o.user_id = u.id`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub request: Option<String>,
     pub from_type: FromType,
-    pub path: Vec<String>,
+    pub path: Vec<PathSegment>,
+    // Matched rows are stored under this key, NULL for unmatched LEFT rows
+    pub alias: String,
+    pub on: Expression,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+}
+
+// `[OUTER] UNNEST items AS item` emits one row per element of `items`,
+// keeping the parent's fields alongside `item`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Unnest {
+    pub path: FieldPath,
+    pub alias: String,
+    // OUTER keeps parents whose array is empty or missing, with `alias` set to NULL
+    pub outer: bool,
+    pub span: Span,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -89,18 +578,188 @@ pub enum FromType {
     Response,
 }
 
+//      == Order By Clause ==
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderByItem {
+    pub expression: Expression,
+    pub direction: SortDirection,
+    // None falls back to NULLS LAST for ASC and NULLS FIRST for DESC
+    pub nulls: Option<NullsOrder>,
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
 //      == Where Clause ==
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Expression {
-    LiteralExpr(Literal),
+    LiteralExpr(Literal, Span),
     FieldPathExpr(FieldPath),
+    UnaryOpExpr {
+        op: UnaryOp,
+        operand: Box<Expression>,
+        span: Span,
+    },
     BinaryOpExpr {
         left: Box<Expression>,
         op: BinaryOp,
         right: Box<Expression>,
+        span: Span,
+    },
+    Aggregate {
+        func: AggregateFunc,
+        // None for COUNT(*)
+        arg: Option<Box<Expression>>,
+        span: Span,
+    },
+    FunctionCall {
+        // Lowercase name of a built-in from `query::lookup_function`
+        name: String,
+        args: Vec<Expression>,
+        span: Span,
+    },
+    // `[a, b]`, or the parenthesised list after IN
+    ArrayExpr {
+        items: Vec<Expression>,
+        span: Span,
+    },
+    InExpr {
+        expr: Box<Expression>,
+        // Either an ArrayExpr or any expression evaluating to an array
+        list: Box<Expression>,
+        negated: bool,
+        span: Span,
+    },
+    BetweenExpr {
+        expr: Box<Expression>,
+        low: Box<Expression>,
+        high: Box<Expression>,
+        negated: bool,
+        span: Span,
+    },
+    LikeExpr {
+        expr: Box<Expression>,
+        pattern: Box<Expression>,
+        // ILIKE
+        case_insensitive: bool,
+        negated: bool,
+        span: Span,
+    },
+    IsNullExpr {
+        expr: Box<Expression>,
+        negated: bool,
+        span: Span,
+    },
+    Case {
+        // `CASE x WHEN 1 THEN ...` compares against x; otherwise each WHEN is a condition
+        operand: Option<Box<Expression>>,
+        branches: Vec<CaseBranch>,
+        // None yields NULL when no branch matches
+        else_result: Option<Box<Expression>>,
+        span: Span,
     },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaseBranch {
+    pub when: Expression,
+    pub then: Expression,
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::LiteralExpr(_, span) => *span,
+            Expression::FieldPathExpr(fp) => fp.span,
+            Expression::UnaryOpExpr { span, .. } => *span,
+            Expression::BinaryOpExpr { span, .. } => *span,
+            Expression::Aggregate { span, .. } => *span,
+            Expression::FunctionCall { span, .. } => *span,
+            Expression::ArrayExpr { span, .. } => *span,
+            Expression::InExpr { span, .. } => *span,
+            Expression::BetweenExpr { span, .. } => *span,
+            Expression::LikeExpr { span, .. } => *span,
+            Expression::IsNullExpr { span, .. } => *span,
+            Expression::Case { span, .. } => *span,
+        }
+    }
+
+    // Span of the first aggregate call inside this expression, if any
+    pub fn aggregate_span(&self) -> Option<Span> {
+        match self {
+            Expression::Aggregate { span, .. } => Some(*span),
+            _ => self.children().into_iter().find_map(|e| e.aggregate_span()),
+        }
+    }
+
+    // Direct subexpressions, in source order
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::LiteralExpr(..) | Expression::FieldPathExpr(_) => Vec::new(),
+            Expression::UnaryOpExpr { operand, .. } => vec![operand],
+            Expression::BinaryOpExpr { left, right, .. } => vec![left, right],
+            Expression::Aggregate { arg, .. } => arg.iter().map(|a| a.as_ref()).collect(),
+            Expression::FunctionCall { args, .. } => args.iter().collect(),
+            Expression::ArrayExpr { items, .. } => items.iter().collect(),
+            Expression::InExpr { expr, list, .. } => vec![expr, list],
+            Expression::BetweenExpr {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expression::LikeExpr { expr, pattern, .. } => vec![expr, pattern],
+            Expression::IsNullExpr { expr, .. } => vec![expr],
+            Expression::Case {
+                operand,
+                branches,
+                else_result,
+                ..
+            } => operand
+                .iter()
+                .map(|e| e.as_ref())
+                .chain(branches.iter().flat_map(|b| [&b.when, &b.then]))
+                .chain(else_result.iter().map(|e| e.as_ref()))
+                .collect(),
+        }
+    }
+
+    pub fn contains_aggregate(&self) -> bool {
+        self.aggregate_span().is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    ArrayAgg,
+}
+
+impl AggregateFunc {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(AggregateFunc::Count),
+            "SUM" => Some(AggregateFunc::Sum),
+            "AVG" => Some(AggregateFunc::Avg),
+            "MIN" => Some(AggregateFunc::Min),
+            "MAX" => Some(AggregateFunc::Max),
+            "ARRAY_AGG" => Some(AggregateFunc::ArrayAgg),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Literal {
     StringLiteral(String),
@@ -109,6 +768,12 @@ pub enum Literal {
     Null,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BinaryOp {
     Eq,
@@ -120,9 +785,48 @@ pub enum BinaryOp {
     And,
     Or,
     RegexMatch,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FieldPath {
-    pub path: Vec<String>,
+    pub path: Vec<PathSegment>,
+    pub span: Span,
+}
+
+impl FieldPath {
+    // Last field name in the path, so `tags[0]` is `tags` and `o.id` is `id`
+    pub fn name(&self) -> Option<&str> {
+        self.path.iter().rev().find_map(|segment| match segment {
+            PathSegment::Key(key) | PathSegment::Descendant(key) => Some(key.as_str()),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PathSegment {
+    Key(String),
+    // `[n]`; negative indexes count from the end
+    Index(i64),
+    // `[*]` or `.*` yields every element, so later segments apply to each
+    Wildcard,
+    // `..name` finds `name` at any depth
+    Descendant(String),
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Key(key) => write!(f, "{}", key),
+            PathSegment::Index(i) => write!(f, "[{}]", i),
+            PathSegment::Wildcard => write!(f, "[*]"),
+            PathSegment::Descendant(key) => write!(f, "..{}", key),
+        }
+    }
 }
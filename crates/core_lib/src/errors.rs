@@ -1,24 +1,46 @@
+use crate::ast::Span;
 use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ParseError {
-    #[error("syntax error at line {line}, column {column}: {message}")]
-    Syntax {
-        line: usize,
-        column: usize,
-        message: ErrorCodes,
-    },
+    #[error("syntax error at {span}: {message}")]
+    Syntax { span: Span, message: ErrorCodes },
     #[error("unexpected end of input")]
     Eof,
 }
 
+impl ParseError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::Syntax { span, .. } => Some(*span),
+            ParseError::Eof => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ErrorCodes {
     UnexpectedToken(String),
     InvalidIdentifier(String),
     UnknownError,
     InvalidLimitValue(String),
+    InvalidOffsetValue(String),
+    InvalidOrderBy(String),
+    InvalidCacheValue(String),
+    UnterminatedString,
+    Expected(String),
+    UnknownFunction(String),
+    WrongArgumentCount(String),
+    MisplacedAggregate(String),
+    UnknownRequest(String),
+    DuplicateResponse(String),
+    DependencyCycle(String),
+    InvalidPaginateValue(String),
+    InvalidStatus(String),
+    InvalidReturnValue(String),
+    InvalidRetryValue(String),
+    InvalidTimeoutValue(String),
 }
 
 impl fmt::Display for ErrorCodes {
@@ -28,6 +50,32 @@ impl fmt::Display for ErrorCodes {
             ErrorCodes::InvalidIdentifier(id) => write!(f, "invalid identifier: {}", id),
             ErrorCodes::UnknownError => write!(f, "unknown error"),
             ErrorCodes::InvalidLimitValue(val) => write!(f, "invalid limit value: {}", val),
+            ErrorCodes::InvalidOffsetValue(val) => write!(f, "invalid offset value: {}", val),
+            ErrorCodes::InvalidOrderBy(val) => write!(f, "invalid ORDER BY item: {}", val),
+            ErrorCodes::InvalidCacheValue(val) => write!(f, "invalid cache value: {}", val),
+            ErrorCodes::UnterminatedString => write!(f, "unterminated string literal"),
+            ErrorCodes::Expected(what) => write!(f, "expected {}", what),
+            ErrorCodes::UnknownFunction(name) => write!(f, "unknown function: {}", name),
+            ErrorCodes::WrongArgumentCount(detail) => {
+                write!(f, "wrong number of arguments: {}", detail)
+            }
+            ErrorCodes::MisplacedAggregate(call) => {
+                write!(f, "aggregate function not allowed here: {}", call)
+            }
+            ErrorCodes::UnknownRequest(name) => write!(f, "unknown request: {}", name),
+            ErrorCodes::DuplicateResponse(name) => {
+                write!(f, "request {} already has a RESPONSE block", name)
+            }
+            ErrorCodes::InvalidPaginateValue(val) => write!(f, "invalid PAGINATE value: {}", val),
+            ErrorCodes::InvalidStatus(val) => write!(f, "invalid HTTP status: {}", val),
+            ErrorCodes::InvalidReturnValue(val) => {
+                write!(f, "RETURN value is not valid JSON: {}", val)
+            }
+            ErrorCodes::InvalidRetryValue(val) => write!(f, "invalid RETRY value: {}", val),
+            ErrorCodes::InvalidTimeoutValue(val) => write!(f, "invalid TIMEOUT value: {}", val),
+            ErrorCodes::DependencyCycle(chain) => {
+                write!(f, "requests depend on each other: {}", chain)
+            }
         }
     }
 }
@@ -42,4 +90,25 @@ pub enum QueryError {
 
     #[error("type error: {message}")]
     TypeError { message: String },
+
+    #[error("division by zero")]
+    DivisionByZero,
+
+    #[error("no response available for request `{request}`")]
+    MissingSource { request: String },
+}
+
+#[derive(Debug, Error)]
+pub enum SignError {
+    #[error(
+        "no AWS credentials: give KEY and SECRET in SIGN or set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+    )]
+    MissingCredentials,
+
+    #[error("cannot sign a request to `{0}`")]
+    InvalidUrl(String),
+
+    // From a signing hook installed by the runtime's embedder
+    #[error("{0}")]
+    Failed(String),
 }
@@ -0,0 +1,291 @@
+use crate::ast::Span;
+use crate::errors::{ErrorCodes, ParseError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // Identifiers and keywords; keywords are matched case-insensitively by the parser
+    Ident(String),
+    Number(f64),
+    Str(String),
+
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Colon,
+    Dot,
+
+    Eq,
+    EqEq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    RegexMatch,
+
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Bang,
+    Concat,
+
+    // Anything else, e.g. `?` or `&` inside a URL. Raw text is recovered through the span.
+    Other(char),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn is_keyword(&self, kw: &str) -> bool {
+        matches!(&self.kind, TokenKind::Ident(s) if s.eq_ignore_ascii_case(kw))
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.chars.get(self.pos + 1).map(|(_, c)| *c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map(|(i, _)| *i)
+            .unwrap_or(self.source.len())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn prev_is_whitespace(&self) -> bool {
+        self.pos == 0 || self.chars[self.pos - 1].1.is_whitespace()
+    }
+
+    // A `'` inside a word, as in `Bob's`, or with no closing quote on its line
+    // is an apostrophe rather than the start of a string
+    fn is_apostrophe(&self) -> bool {
+        if self.pos > 0 && self.chars[self.pos - 1].1.is_alphanumeric() {
+            return true;
+        }
+        let mut rest = self.chars[self.pos + 1..].iter().map(|(_, c)| *c);
+        while let Some(c) = rest.next() {
+            match c {
+                '\\' => {
+                    rest.next();
+                }
+                '\'' => return false,
+                '\n' => return true,
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.bump();
+                continue;
+            }
+
+            // `#` only starts a comment at the start of a word, so URLs and
+            // header values such as `page#section` survive intact.
+            if c == '#' && self.prev_is_whitespace() {
+                while let Some(c) = self.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.bump();
+                }
+                continue;
+            }
+
+            let start = self.offset();
+            let line = self.line;
+            let column = self.column;
+
+            let kind = if c.is_alphabetic() || c == '_' {
+                self.lex_ident()
+            } else if c.is_ascii_digit() {
+                self.lex_number()
+            } else if c == '"' || (c == '\'' && !self.is_apostrophe()) {
+                self.lex_string(start, line, column)?
+            } else {
+                self.lex_symbol()
+            };
+
+            tokens.push(Token {
+                kind,
+                span: Span {
+                    start,
+                    end: self.offset(),
+                    line,
+                    column,
+                },
+            });
+        }
+
+        let end = self.source.len();
+        tokens.push(Token {
+            kind: TokenKind::Eof,
+            span: Span {
+                start: end,
+                end,
+                line: self.line,
+                column: self.column,
+            },
+        });
+
+        Ok(tokens)
+    }
+
+    fn lex_ident(&mut self) -> TokenKind {
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        TokenKind::Ident(self.source[start..self.offset()].to_string())
+    }
+
+    fn lex_number(&mut self) -> TokenKind {
+        let start = self.offset();
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+            self.bump();
+        }
+        if self.peek() == Some('.') && matches!(self.peek_next(), Some(c) if c.is_ascii_digit()) {
+            self.bump();
+            while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+                self.bump();
+            }
+        }
+
+        let text = &self.source[start..self.offset()];
+        // Only digits and a single dot were consumed, so this cannot fail
+        TokenKind::Number(text.parse().unwrap_or_default())
+    }
+
+    fn lex_string(
+        &mut self,
+        start: usize,
+        line: usize,
+        column: usize,
+    ) -> Result<TokenKind, ParseError> {
+        let quote = self.bump().unwrap_or('"');
+        let mut value = String::new();
+
+        // Strings end at the newline, so a stray quote cannot swallow the lines after it
+        while self.peek() != Some('\n') {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(TokenKind::Str(value)),
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+
+        Err(ParseError::Syntax {
+            span: Span {
+                start,
+                end: self.offset(),
+                line,
+                column,
+            },
+            message: ErrorCodes::UnterminatedString,
+        })
+    }
+
+    fn lex_symbol(&mut self) -> TokenKind {
+        let c = self.bump().unwrap_or_default();
+        let next = self.peek();
+
+        let two = |lexer: &mut Self, kind: TokenKind| {
+            lexer.bump();
+            kind
+        };
+
+        match (c, next) {
+            ('>', Some('=')) => two(self, TokenKind::Gte),
+            ('<', Some('=')) => two(self, TokenKind::Lte),
+            ('!', Some('=')) => two(self, TokenKind::Neq),
+            ('=', Some('=')) => two(self, TokenKind::EqEq),
+            ('=', Some('~')) => two(self, TokenKind::RegexMatch),
+            ('|', Some('|')) => two(self, TokenKind::Concat),
+            ('>', _) => TokenKind::Gt,
+            ('<', _) => TokenKind::Lt,
+            ('=', _) => TokenKind::Eq,
+            ('!', _) => TokenKind::Bang,
+            ('{', _) => TokenKind::LBrace,
+            ('}', _) => TokenKind::RBrace,
+            ('(', _) => TokenKind::LParen,
+            (')', _) => TokenKind::RParen,
+            ('[', _) => TokenKind::LBracket,
+            (']', _) => TokenKind::RBracket,
+            (',', _) => TokenKind::Comma,
+            (':', _) => TokenKind::Colon,
+            ('.', _) => TokenKind::Dot,
+            ('*', _) => TokenKind::Star,
+            ('+', _) => TokenKind::Plus,
+            ('-', _) => TokenKind::Minus,
+            ('/', _) => TokenKind::Slash,
+            ('%', _) => TokenKind::Percent,
+            (other, _) => TokenKind::Other(other),
+        }
+    }
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(source).tokenize()
+}
@@ -1,9 +1,17 @@
 pub mod ast;
+pub mod errors;
+pub mod lexer;
+pub mod paginate;
 pub mod parser;
 pub mod query;
-pub mod errors;
+pub mod request;
+pub mod sign;
 
 pub use ast::*;
+pub use errors::*;
+pub use lexer::*;
+pub use paginate::*;
 pub use parser::*;
 pub use query::*;
-pub use errors::*;
+pub use request::*;
+pub use sign::*;
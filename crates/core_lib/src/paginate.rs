@@ -0,0 +1,258 @@
+use crate::ast::*;
+use crate::errors::QueryError;
+use crate::query::{is_aggregate_query, json_type_name, resolve_path};
+use crate::request::set_query_param;
+use serde_json::Value;
+use url::Url;
+
+// == Page Plan ==
+// Where a paginated request's items live and how many of them are needed,
+// taken from the queries that read the request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PagePlan {
+    pub items_path: Vec<PathSegment>,
+    // Stop once this many items are in, set when a single LIMIT query without
+    // ORDER BY reads the request
+    pub target: Option<usize>,
+}
+
+pub fn page_plan(program: &Program, request: &RequestBlock) -> PagePlan {
+    let mut reads: Vec<(&QueryBlock, &[PathSegment], bool)> = Vec::new();
+    for response in &program.response_blocks {
+        let query = &response.query;
+        let from = &query.from_clause;
+        let own = from.request.is_none()
+            && program
+                .request_for(response)
+                .is_some_and(|r| r.name == request.name);
+        if (own || from.request.as_deref() == Some(&request.name))
+            && matches!(from.from_type, FromType::Body)
+        {
+            reads.push((query, &from.path, true));
+        }
+        for join in &from.joins {
+            if join.request.as_deref() == Some(&request.name) {
+                reads.push((query, &join.path, false));
+            }
+        }
+    }
+    let fanned_out = program
+        .request_blocks
+        .iter()
+        .filter_map(|r| r.for_each.as_ref())
+        .any(|f| f.request == request.name);
+
+    let target = match reads.as_slice() {
+        [(query, _, true)] if !fanned_out => row_target(query),
+        _ => None,
+    };
+    PagePlan {
+        items_path: reads
+            .first()
+            .map(|(_, path, _)| path.to_vec())
+            .unwrap_or_default(),
+        target,
+    }
+}
+
+// Rows a query can return from its first n input rows: only a plain LIMIT
+// without ORDER BY, filtering or reshaping of rows can stop early
+fn row_target(query: &QueryBlock) -> Option<usize> {
+    let from = &query.from_clause;
+    let limit = query.limit?;
+    let plain = query.order_by.is_empty()
+        && query.where_clause.is_none()
+        && query.distinct.is_none()
+        && from.joins.is_empty()
+        && from.unnest.is_empty()
+        && !is_aggregate_query(query);
+    plain.then(|| query.offset.unwrap_or(0) as usize + limit as usize)
+}
+
+// == Paginator ==
+// Drives one paginated request: fetch `next_url`, hand the page to `add_page`,
+// repeat until `next_url` is None, then `finish` for the merged body.
+pub struct Paginator<'a> {
+    paginate: &'a Paginate,
+    url: String,
+    plan: PagePlan,
+    first: Option<Value>,
+    items: Vec<Value>,
+    pages: u32,
+    current: String,
+    next: Option<String>,
+}
+
+impl<'a> Paginator<'a> {
+    pub fn new(request: &RequestBlock, paginate: &'a Paginate, plan: PagePlan) -> Self {
+        let url = request.full_url();
+        let first_url = match &paginate.strategy {
+            PageStrategy::Page { param, start } => set_query_param(&url, param, &start.to_string()),
+            PageStrategy::Offset { param } => set_query_param(&url, param, "0"),
+            PageStrategy::Cursor { .. } | PageStrategy::Link => url.clone(),
+        };
+        Self {
+            paginate,
+            url,
+            plan,
+            first: None,
+            items: Vec::new(),
+            pages: 0,
+            current: first_url.clone(),
+            next: Some(first_url),
+        }
+    }
+
+    pub fn next_url(&self) -> Option<&str> {
+        self.next.as_deref()
+    }
+
+    pub fn add_page(
+        &mut self,
+        body: Value,
+        headers: &[(String, String)],
+    ) -> Result<(), QueryError> {
+        if let Some(url) = self.next.take() {
+            self.current = url;
+        }
+        let items = match resolve_path(&body, &self.plan.items_path)? {
+            Value::Array(items) => items,
+            Value::Null => Vec::new(),
+            other => {
+                return Err(QueryError::ExpectedArray {
+                    path: path_text(&self.plan.items_path),
+                    found: json_type_name(&other).to_string(),
+                });
+            }
+        };
+        let empty = items.is_empty();
+        self.items.extend(items);
+        self.pages += 1;
+
+        let max_items = self.paginate.max_items.map(|n| n as usize);
+        if let Some(max) = max_items {
+            self.items.truncate(max);
+        }
+        let done = empty
+            || self.paginate.max_pages.is_some_and(|max| self.pages >= max)
+            || max_items.is_some_and(|max| self.items.len() >= max)
+            || self.plan.target.is_some_and(|n| self.items.len() >= n);
+
+        if !done {
+            self.next = match &self.paginate.strategy {
+                PageStrategy::Page { param, start } => Some(set_query_param(
+                    &self.url,
+                    param,
+                    &(start + self.pages).to_string(),
+                )),
+                PageStrategy::Offset { param } => Some(set_query_param(
+                    &self.url,
+                    param,
+                    &self.items.len().to_string(),
+                )),
+                PageStrategy::Cursor { param, path } => {
+                    match resolve_path(&body, path).unwrap_or(Value::Null) {
+                        Value::Null => None,
+                        Value::String(s) if s.is_empty() => None,
+                        Value::String(s) => Some(set_query_param(&self.url, param, &s)),
+                        other => Some(set_query_param(&self.url, param, &other.to_string())),
+                    }
+                }
+                PageStrategy::Link => next_link(headers, &self.current),
+            };
+        }
+
+        if self.first.is_none() {
+            self.first = Some(body);
+        }
+        Ok(())
+    }
+
+    // The first page with every page's items concatenated under the items path
+    pub fn finish(self) -> Result<Value, QueryError> {
+        let mut body = self.first.unwrap_or(Value::Null);
+        if self.plan.items_path.is_empty() {
+            return Ok(Value::Array(self.items));
+        }
+        match slot_mut(&mut body, &self.plan.items_path) {
+            Some(slot) => *slot = Value::Array(self.items),
+            None => {
+                return Err(QueryError::TypeError {
+                    message: format!(
+                        "cannot merge pages under path `{}`",
+                        path_text(&self.plan.items_path)
+                    ),
+                });
+            }
+        }
+        Ok(body)
+    }
+}
+
+fn slot_mut<'v>(value: &'v mut Value, path: &[PathSegment]) -> Option<&'v mut Value> {
+    let mut current = value;
+    for segment in path {
+        current = match segment {
+            PathSegment::Key(key) => current.as_object_mut()?.get_mut(key)?,
+            PathSegment::Index(index) => {
+                let items = current.as_array_mut()?;
+                let index = if *index < 0 {
+                    items.len().checked_sub(index.unsigned_abs() as usize)?
+                } else {
+                    *index as usize
+                };
+                items.get_mut(index)?
+            }
+            PathSegment::Wildcard | PathSegment::Descendant(_) => return None,
+        };
+    }
+    Some(current)
+}
+
+fn path_text(path: &[PathSegment]) -> String {
+    let mut text = String::from("body");
+    for segment in path {
+        if let PathSegment::Key(_) = segment {
+            text.push('.');
+        }
+        text.push_str(&segment.to_string());
+    }
+    text
+}
+
+// == Link Header ==
+
+// The `rel="next"` target of an RFC 5988 Link header, resolved against `current`
+pub fn next_link(headers: &[(String, String)], current: &str) -> Option<String> {
+    let mut rest = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("link"))
+        .map(|(_, value)| value.as_str())?;
+
+    // Links are split at their `<...>` targets, which may hold commas of their own
+    while let Some(start) = rest.find('<') {
+        let end = start + rest[start..].find('>')?;
+        let target = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        let params = &rest[..rest.find('<').unwrap_or(rest.len())];
+        let is_next = params.split([';', ',']).any(|param| {
+            param.trim().strip_prefix("rel=").is_some_and(|rel| {
+                rel.trim_matches('"')
+                    .split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("next"))
+            })
+        });
+        if is_next {
+            return resolve_url(current, target);
+        }
+    }
+    None
+}
+
+fn resolve_url(current: &str, target: &str) -> Option<String> {
+    Url::parse(current)
+        .ok()?
+        .join(target)
+        .ok()
+        .map(String::from)
+}
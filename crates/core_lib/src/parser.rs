@@ -1,354 +1,1775 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
 
 use crate::ast::*;
 use crate::errors::{ErrorCodes, ParseError};
+use crate::lexer::{Token, TokenKind, tokenize};
+use crate::query::{lookup_function, request_references};
+use crate::request::replace_placeholders;
 use regex::Regex;
 
-// --- HELPER FUNCTIONS ---
-pub fn is_toplevel_keyword(line: &str) -> bool {
-    let s_up = line.to_uppercase();
-    s_up == "USING" || s_up.starts_with("REQUEST ") || s_up == "RESPONSE"
+const TOP_LEVEL_KEYWORDS: &[&str] = &["USING", "REQUEST", "RESPONSE"];
+const CLAUSE_KEYWORDS: &[&str] = &[
+    "FROM", "WHERE", "GROUP", "HAVING", "ORDER", "SELECT", "LIMIT", "OFFSET",
+];
+const OPERATOR_KEYWORDS: &[&str] = &["AND", "OR", "NOT", "IN", "BETWEEN", "LIKE", "ILIKE", "IS"];
+const EXPRESSION_KEYWORDS: &[&str] = &["CASE"];
+// Keywords that may follow NOT after an operand, as in `a NOT IN (...)`
+const NEGATABLE_PREDICATES: &[&str] = &["IN", "BETWEEN", "LIKE", "ILIKE"];
+
+// RETRY defaults: statuses worth another try, and the first wait between tries
+const DEFAULT_RETRY_STATUSES: &[u16] = &[429, 502, 503, 504];
+const DEFAULT_BACKOFF_MS: u64 = 500;
+
+// SIGN HMAC-SHA256 defaults
+const DEFAULT_SIGNATURE_HEADER: &str = "X-Signature";
+const DEFAULT_TIMESTAMP_HEADER: &str = "X-Timestamp";
+const DEFAULT_SIGNATURE_TEMPLATE: &str = "{method}\n{path}\n{timestamp}\n{body}";
+
+// Binding power of binary and prefix operators, loosest first
+const PREC_OR: u8 = 1;
+const PREC_AND: u8 = 2;
+const PREC_NOT: u8 = 3;
+const PREC_COMPARISON: u8 = 4;
+const PREC_CONCAT: u8 = 5;
+const PREC_ADDITIVE: u8 = 6;
+const PREC_MULTIPLICATIVE: u8 = 7;
+
+fn is_reserved(name: &str) -> bool {
+    TOP_LEVEL_KEYWORDS
+        .iter()
+        .chain(CLAUSE_KEYWORDS)
+        .chain(OPERATOR_KEYWORDS)
+        .chain(EXPRESSION_KEYWORDS)
+        .any(|kw| name.eq_ignore_ascii_case(kw))
+}
+
+fn method_from_keyword(token: &Token) -> Option<HttpMethods> {
+    match &token.kind {
+        TokenKind::Ident(s) => match s.to_uppercase().as_str() {
+            "GET" => Some(HttpMethods::Get),
+            "POST" => Some(HttpMethods::Post),
+            "PUT" => Some(HttpMethods::Put),
+            "DELETE" => Some(HttpMethods::Delete),
+            "PATCH" => Some(HttpMethods::Patch),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
-pub fn trim_quotes(s: &str) -> String {
-    let s = s.trim();
-    if (s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')) {
-        s[1..s.len() - 1].to_string()
-    } else {
-        s.to_string()
+// --- PARSER ---
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    // Declared REQUEST names, collected up front so `FROM GetUsers.body` can
+    // refer to a request defined further down
+    request_names: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let request_names = tokens
+            .windows(2)
+            .filter(|pair| pair[0].is_keyword("REQUEST"))
+            .filter_map(|pair| match &pair[1].kind {
+                TokenKind::Ident(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Self {
+            source,
+            tokens,
+            pos: 0,
+            request_names,
+        })
+    }
+
+    // --- TOKEN HELPERS ---
+    fn peek(&self) -> &Token {
+        // The token stream always ends with Eof
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn prev_span(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].span
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().kind == TokenKind::Eof
+    }
+
+    fn at_keyword(&self, kw: &str) -> bool {
+        self.peek().is_keyword(kw)
+    }
+
+    fn at_block_start(&self) -> bool {
+        self.at_eof() || TOP_LEVEL_KEYWORDS.iter().any(|kw| self.at_keyword(kw))
+    }
+
+    fn on_line(&self, line: usize) -> bool {
+        !self.at_eof() && self.peek().span.line == line
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> Option<Token> {
+        if &self.peek().kind == kind {
+            Some(self.advance())
+        } else {
+            None
+        }
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> Option<Token> {
+        if self.at_keyword(kw) {
+            Some(self.advance())
+        } else {
+            None
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind, what: &str) -> Result<Token, ParseError> {
+        self.eat(kind)
+            .ok_or_else(|| self.error(self.peek(), ErrorCodes::Expected(what.to_string())))
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<Token, ParseError> {
+        self.eat_keyword(kw)
+            .ok_or_else(|| self.error(self.peek(), ErrorCodes::Expected(kw.to_string())))
+    }
+
+    fn expect_ident(&mut self, what: &str) -> Result<(String, Span), ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Ident(name) => {
+                self.advance();
+                Ok((name, token.span))
+            }
+            TokenKind::Eof => Err(self.error(&token, ErrorCodes::Expected(what.to_string()))),
+            _ => Err(self.error(
+                &token,
+                ErrorCodes::InvalidIdentifier(self.text(&token).to_string()),
+            )),
+        }
+    }
+
+    fn text(&self, token: &Token) -> &'a str {
+        &self.source[token.span.start..token.span.end]
+    }
+
+    fn error(&self, token: &Token, message: ErrorCodes) -> ParseError {
+        ParseError::Syntax {
+            span: token.span,
+            message,
+        }
+    }
+
+    fn unexpected(&self) -> ParseError {
+        let token = self.peek();
+        if token.kind == TokenKind::Eof {
+            return self.error(token, ErrorCodes::Expected("more input".to_string()));
+        }
+        self.error(
+            token,
+            ErrorCodes::UnexpectedToken(self.text(token).to_string()),
+        )
+    }
+
+    // Consumes the remaining tokens on `line` and returns their raw source text.
+    // A lone quoted string yields its unquoted value instead.
+    fn rest_of_line(&mut self, line: usize) -> Option<(String, Span)> {
+        let first = self.peek().clone();
+        let mut last = first.clone();
+        let mut count = 0;
+        while self.on_line(line) {
+            last = self.advance();
+            count += 1;
+        }
+
+        match (count, &first.kind) {
+            (0, _) => None,
+            (1, TokenKind::Str(value)) => Some((value.clone(), first.span)),
+            _ => {
+                let span = first.span.to(last.span);
+                Some((self.source[span.start..span.end].to_string(), span))
+            }
+        }
+    }
+
+    // --- PROGRAM ---
+    fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut using_block: Option<UsingBlock> = None;
+        let mut request_blocks: Vec<RequestBlock> = Vec::new();
+        let mut response_blocks: Vec<ResponseBlock> = Vec::new();
+
+        while !self.at_eof() {
+            if self.at_keyword("USING") {
+                let block = self.parse_using()?;
+                match &mut using_block {
+                    Some(existing) => {
+                        existing.var_declarations.extend(block.var_declarations);
+                        existing.span = existing.span.to(block.span);
+                    }
+                    None => using_block = Some(block),
+                }
+            } else if self.at_keyword("REQUEST") {
+                request_blocks.push(self.parse_request()?);
+            } else if self.at_keyword("RESPONSE") {
+                response_blocks.push(self.parse_response()?);
+            } else {
+                return Err(self.unexpected());
+            }
+        }
+
+        let program = Program {
+            using_block,
+            request_blocks,
+            response_blocks,
+        };
+        self.check_bindings(&program)?;
+        self.check_dependencies(&program)?;
+        Ok(program)
+    }
+
+    // Every named request must exist, and each request feeds at most one
+    // RESPONSE since results are keyed by request name.
+    fn check_bindings(&self, program: &Program) -> Result<(), ParseError> {
+        let mut bound: Vec<&str> = Vec::new();
+        for response in &program.response_blocks {
+            match program.request_for(response) {
+                Some(request) if bound.contains(&request.name.as_str()) => {
+                    return Err(ParseError::Syntax {
+                        span: response.request.as_ref().map_or(response.span, |r| r.span),
+                        message: ErrorCodes::DuplicateResponse(request.name.clone()),
+                    });
+                }
+                Some(request) => bound.push(&request.name),
+                None => {
+                    if let Some(request) = &response.request {
+                        return Err(ParseError::Syntax {
+                            span: request.span,
+                            message: ErrorCodes::UnknownRequest(request.name.clone()),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Requests run in dependency order, so they must not reference each other
+    // in a loop.
+    fn check_dependencies(&self, program: &Program) -> Result<(), ParseError> {
+        match program.request_order() {
+            Ok(_) => Ok(()),
+            Err(chain) => {
+                let span = program
+                    .request_blocks
+                    .iter()
+                    .find(|r| r.name == chain[0])
+                    .map_or_else(|| self.peek().span, |r| r.span);
+                Err(ParseError::Syntax {
+                    span,
+                    message: ErrorCodes::DependencyCycle(chain.join(" -> ")),
+                })
+            }
+        }
+    }
+
+    // --- USING BLOCK ---
+    fn parse_using(&mut self) -> Result<UsingBlock, ParseError> {
+        let start = self.expect_keyword("USING")?.span;
+        let mut var_declarations = Vec::new();
+
+        while !self.at_block_start() {
+            let (name, name_span) = self.expect_ident("variable name")?;
+            self.expect(&TokenKind::Colon, "':' after variable name")?;
+            let (value, value_span) = self.rest_of_line(name_span.line).ok_or_else(|| {
+                self.error(
+                    self.peek(),
+                    ErrorCodes::Expected("variable value".to_string()),
+                )
+            })?;
+
+            var_declarations.push(VarDeclaration {
+                name,
+                value,
+                span: name_span.to(value_span),
+            });
+        }
+
+        Ok(UsingBlock {
+            var_declarations,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // --- REQUEST BLOCK ---
+    fn parse_request(&mut self) -> Result<RequestBlock, ParseError> {
+        let start = self.expect_keyword("REQUEST")?.span;
+        if !self.on_line(start.line) {
+            return Err(self.error(
+                self.peek(),
+                ErrorCodes::Expected("request name".to_string()),
+            ));
+        }
+        let (name, _) = self.expect_ident("request name")?;
+        let for_each = if self.on_line(start.line) && self.at_keyword("FOR") {
+            Some(self.parse_for_each()?)
+        } else {
+            None
+        };
+
+        let mut method = HttpMethods::Get;
+        let mut url = String::new();
+        let mut headers: Vec<Header> = Vec::new();
+        let mut query_params: Vec<Param> = Vec::new();
+        let mut body: Option<RequestBody> = None;
+        let mut cache = CacheDuration::None;
+        let mut paginate: Option<Paginate> = None;
+        let mut expect_status: Vec<StatusRange> = Vec::new();
+        let mut on_status: Vec<OnStatus> = Vec::new();
+        let mut retry: Option<Retry> = None;
+        let mut timeout_ms: Option<u64> = None;
+        let mut auth: Option<Auth> = None;
+        let mut sign: Option<Sign> = None;
+
+        while !self.at_block_start() {
+            let token = self.peek().clone();
+
+            if let Some(m) = method_from_keyword(&token) {
+                self.advance();
+                let (u, _) = self.rest_of_line(token.span.line).ok_or_else(|| {
+                    self.error(&token, ErrorCodes::Expected("URL after method".to_string()))
+                })?;
+                method = m;
+                url = u;
+            } else if token.is_keyword("HEADER") || token.is_keyword("HEADERS") {
+                self.advance();
+                if self.on_line(token.span.line) {
+                    // HEADER Key: Value
+                    headers.push(self.parse_header()?);
+                } else {
+                    // HEADERS followed by one `Key: Value` per line
+                    while self.at_header_line() {
+                        headers.push(self.parse_header()?);
+                    }
+                }
+            } else if token.is_keyword("QUERY") {
+                self.advance();
+                if self.on_line(token.span.line) {
+                    // QUERY key: value
+                    query_params.push(self.parse_param("query parameter")?);
+                } else {
+                    // QUERY followed by one `key: value` per line; keys may repeat
+                    while self.at_header_line() {
+                        query_params.push(self.parse_param("query parameter")?);
+                    }
+                }
+            } else if token.is_keyword("BODY") {
+                body = Some(self.parse_body()?);
+            } else if token.is_keyword("PAGINATE") {
+                if for_each.is_some() {
+                    return Err(self.error(
+                        &token,
+                        ErrorCodes::UnexpectedToken("PAGINATE in a FOR EACH request".to_string()),
+                    ));
+                }
+                paginate = Some(self.parse_paginate()?);
+            } else if token.is_keyword("EXPECT") {
+                self.advance();
+                self.expect_keyword("STATUS")?;
+                expect_status.extend(self.parse_statuses()?);
+            } else if token.is_keyword("ON") {
+                on_status.push(self.parse_on_status()?);
+            } else if token.is_keyword("RETRY") {
+                retry = Some(self.parse_retry()?);
+            } else if token.is_keyword("AUTH") {
+                auth = Some(self.parse_auth()?);
+            } else if token.is_keyword("SIGN") {
+                sign = Some(self.parse_sign()?);
+            } else if token.is_keyword("TIMEOUT") {
+                self.advance();
+                timeout_ms = Some(self.parse_duration_ms(token.span.line)?);
+            } else if token.is_keyword("CACHE") {
+                self.advance();
+                let value = self.peek().clone();
+                match value.kind {
+                    TokenKind::Number(n)
+                        if self.on_line(token.span.line) && n >= 0.0 && n.fract() == 0.0 =>
+                    {
+                        self.advance();
+                        cache = CacheDuration::DurationInSeconds(n as u64);
+                    }
+                    _ => {
+                        return Err(self.error(
+                            &value,
+                            ErrorCodes::InvalidCacheValue(self.text(&value).to_string()),
+                        ));
+                    }
+                }
+            } else {
+                return Err(self.unexpected());
+            }
+        }
+
+        let mut request = RequestBlock {
+            name,
+            method,
+            url,
+            headers,
+            query_params,
+            body,
+            cache,
+            for_each,
+            paginate,
+            expect_status,
+            on_status,
+            retry,
+            timeout_ms,
+            auth,
+            sign,
+            depends_on: Vec::new(),
+            span: start.to(self.prev_span()),
+        };
+        let mut depends_on: Vec<String> =
+            request.for_each.iter().map(|f| f.request.clone()).collect();
+        for dep in request.texts().into_iter().flat_map(request_references) {
+            if self.request_names.contains(&dep) && !depends_on.contains(&dep) {
+                depends_on.push(dep);
+            }
+        }
+        request.depends_on = depends_on;
+        Ok(request)
+    }
+
+    // BODY JSON followed by a JSON document, which may span several lines and
+    // hold `{var}` placeholders; BODY FORM followed by one `key: value` per line;
+    // BODY RAW followed by the rest of the line or a quoted string.
+    fn parse_body(&mut self) -> Result<RequestBody, ParseError> {
+        let keyword = self.expect_keyword("BODY")?;
+        let bracket = matches!(self.peek().kind, TokenKind::LBrace | TokenKind::LBracket);
+        if bracket || self.eat_keyword("JSON").is_some() {
+            return Ok(RequestBody::Json(self.parse_json_text()?));
+        }
+
+        if self.eat_keyword("FORM").is_some() {
+            let mut params = Vec::new();
+            while self.at_header_line() {
+                params.push(self.parse_param("form field")?);
+            }
+            return Ok(RequestBody::Form(params));
+        }
+
+        if let Some(raw) = self.eat_keyword("RAW") {
+            let (text, _) = self.rest_of_line(raw.span.line).ok_or_else(|| {
+                self.error(
+                    &raw,
+                    ErrorCodes::Expected("text after BODY RAW".to_string()),
+                )
+            })?;
+            return Ok(RequestBody::Raw(text));
+        }
+
+        Err(self.error(
+            &keyword,
+            ErrorCodes::Expected("JSON, FORM or RAW after BODY".to_string()),
+        ))
+    }
+
+    // The source text of a bracketed JSON document, matched on brackets
+    fn parse_json_text(&mut self) -> Result<String, ParseError> {
+        let first = self.peek().clone();
+        if !matches!(first.kind, TokenKind::LBrace | TokenKind::LBracket) {
+            return Err(self.error(&first, ErrorCodes::Expected("'{' or '['".to_string())));
+        }
+
+        let mut depth = 0usize;
+        loop {
+            let token = self.advance();
+            match token.kind {
+                TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+                TokenKind::RBrace | TokenKind::RBracket => depth -= 1,
+                // Point at the bracket left open rather than the end of input
+                TokenKind::Eof => {
+                    let close = if first.kind == TokenKind::LBrace {
+                        '}'
+                    } else {
+                        ']'
+                    };
+                    return Err(self.error(
+                        &first,
+                        ErrorCodes::Expected(format!("'{}' to close this JSON", close)),
+                    ));
+                }
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(self.source[first.span.start..token.span.end].to_string());
+            }
+        }
+    }
+
+    // FOR EACH p IN GetPokemon.body.results
+    fn parse_for_each(&mut self) -> Result<ForEach, ParseError> {
+        let start = self.expect_keyword("FOR")?.span;
+        self.expect_keyword("EACH")?;
+        let (alias, _) = self.expect_ident("item name after FOR EACH")?;
+        self.expect_keyword("IN")?;
+
+        let token = self.peek().clone();
+        let (request, from_type, path, span) = self.parse_source()?;
+        let Some(request) = request else {
+            return Err(self.error(
+                &token,
+                ErrorCodes::Expected("a request such as GetUsers.body after IN".to_string()),
+            ));
+        };
+
+        Ok(ForEach {
+            alias,
+            request,
+            from_type,
+            path,
+            span: start.to(span),
+        })
+    }
+
+    // PAGINATE PAGE page [START n] | OFFSET offset | CURSOR cursor FROM body.path | LINK
+    // followed by MAX PAGES n and/or MAX ITEMS n
+    fn parse_paginate(&mut self) -> Result<Paginate, ParseError> {
+        let start = self.expect_keyword("PAGINATE")?.span;
+        let token = self.advance();
+        let strategy = if token.is_keyword("PAGE") {
+            let param = self.parse_param_name()?;
+            let start = if self.eat_keyword("START").is_some() {
+                self.parse_count(ErrorCodes::InvalidPaginateValue)?
+            } else {
+                1
+            };
+            PageStrategy::Page { param, start }
+        } else if token.is_keyword("OFFSET") {
+            PageStrategy::Offset {
+                param: self.parse_param_name()?,
+            }
+        } else if token.is_keyword("CURSOR") {
+            let param = self.parse_param_name()?;
+            self.expect_keyword("FROM")?;
+            let path_token = self.peek().clone();
+            let (request, from_type, path, _) = self.parse_source()?;
+            if request.is_some() || !matches!(from_type, FromType::Body) {
+                return Err(self.error(
+                    &path_token,
+                    ErrorCodes::Expected("a body path after FROM".to_string()),
+                ));
+            }
+            PageStrategy::Cursor { param, path }
+        } else if token.is_keyword("LINK") {
+            PageStrategy::Link
+        } else {
+            return Err(self.error(
+                &token,
+                ErrorCodes::InvalidPaginateValue(self.text(&token).to_string()),
+            ));
+        };
+
+        let mut max_pages = None;
+        let mut max_items = None;
+        while self.eat_keyword("MAX").is_some() {
+            let what = self.advance();
+            if what.is_keyword("PAGES") {
+                max_pages = Some(self.parse_count(ErrorCodes::InvalidPaginateValue)?);
+            } else if what.is_keyword("ITEMS") {
+                max_items = Some(self.parse_count(ErrorCodes::InvalidPaginateValue)?);
+            } else {
+                return Err(self.error(&what, ErrorCodes::Expected("PAGES or ITEMS".to_string())));
+            }
+        }
+        if max_pages.is_none() && max_items.is_none() {
+            return Err(self.error(
+                self.peek(),
+                ErrorCodes::Expected("MAX PAGES or MAX ITEMS after PAGINATE".to_string()),
+            ));
+        }
+
+        Ok(Paginate {
+            strategy,
+            max_pages,
+            max_items,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // ON STATUS 404, 410 RETURN [] where the value is JSON or NULL
+    fn parse_on_status(&mut self) -> Result<OnStatus, ParseError> {
+        let start = self.expect_keyword("ON")?.span;
+        self.expect_keyword("STATUS")?;
+        let statuses = self.parse_statuses()?;
+        self.expect_keyword("RETURN")?;
+
+        let token = self.peek().clone();
+        let value = if self.eat_keyword("NULL").is_some() {
+            serde_json::Value::Null
+        } else {
+            let text = self.parse_json_text()?;
+            serde_json::from_str(&text)
+                .map_err(|_| self.error(&token, ErrorCodes::InvalidReturnValue(text)))?
+        };
+
+        Ok(OnStatus {
+            statuses,
+            value,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // RETRY 3 [BACKOFF 500] [ON 429, 503], all on one line
+    fn parse_retry(&mut self) -> Result<Retry, ParseError> {
+        let start = self.expect_keyword("RETRY")?.span;
+        let retries = self.parse_count(ErrorCodes::InvalidRetryValue)?;
+        let backoff_ms = if self.on_line(start.line) && self.eat_keyword("BACKOFF").is_some() {
+            self.parse_count(ErrorCodes::InvalidRetryValue)? as u64
+        } else {
+            DEFAULT_BACKOFF_MS
+        };
+        let statuses = if self.on_line(start.line) && self.eat_keyword("ON").is_some() {
+            self.parse_statuses()?
+        } else {
+            DEFAULT_RETRY_STATUSES
+                .iter()
+                .map(|&status| StatusRange {
+                    start: status,
+                    end: status,
+                })
+                .collect()
+        };
+
+        Ok(Retry {
+            retries,
+            backoff_ms,
+            statuses,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // AUTH BASIC user pass | BEARER token | APIKEY header|query name value |
+    // OAUTH2 CLIENT_CREDENTIALS token_url client_id secret [scope], on one line
+    fn parse_auth(&mut self) -> Result<Auth, ParseError> {
+        let start = self.expect_keyword("AUTH")?.span;
+        let line = start.line;
+        let token = self.peek().clone();
+        if !self.on_line(line) {
+            return Err(self.error(
+                &token,
+                ErrorCodes::Expected("BASIC, BEARER, APIKEY or OAUTH2 after AUTH".to_string()),
+            ));
+        }
+        self.advance();
+
+        let scheme = if token.is_keyword("BASIC") {
+            AuthScheme::Basic {
+                user: self.parse_word(line, "user after BASIC")?,
+                password: self.parse_word(line, "password after the user")?,
+            }
+        } else if token.is_keyword("BEARER") {
+            AuthScheme::Bearer {
+                token: self.parse_word(line, "token after BEARER")?,
+            }
+        } else if token.is_keyword("APIKEY") {
+            let location = self.peek().clone();
+            let location = if self.on_line(line) && self.eat_keyword("HEADER").is_some() {
+                ApiKeyLocation::Header
+            } else if self.on_line(line) && self.eat_keyword("QUERY").is_some() {
+                ApiKeyLocation::Query
+            } else {
+                return Err(self.error(
+                    &location,
+                    ErrorCodes::Expected("header or query after APIKEY".to_string()),
+                ));
+            };
+            AuthScheme::ApiKey {
+                location,
+                name: self.parse_word(line, "key name")?,
+                value: self.parse_word(line, "key value after the name")?,
+            }
+        } else if token.is_keyword("OAUTH2") {
+            self.expect_keyword("CLIENT_CREDENTIALS")?;
+            AuthScheme::OAuth2 {
+                token_url: self.parse_word(line, "token URL")?,
+                client_id: self.parse_word(line, "client id after the token URL")?,
+                secret: self.parse_word(line, "client secret after the client id")?,
+                scope: if self.on_line(line) {
+                    Some(self.parse_word(line, "scope")?)
+                } else {
+                    None
+                },
+            }
+        } else {
+            return Err(self.error(
+                &token,
+                ErrorCodes::Expected("BASIC, BEARER, APIKEY or OAUTH2 after AUTH".to_string()),
+            ));
+        };
+
+        Ok(Auth {
+            scheme,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // SIGN HMAC-SHA256 secret [HEADER name] [OVER "template"] |
+    // AWS_SIGV4 service region [KEY id SECRET key [TOKEN session]], on one line
+    fn parse_sign(&mut self) -> Result<Sign, ParseError> {
+        let start = self.expect_keyword("SIGN")?.span;
+        let line = start.line;
+        let token = self.peek().clone();
+        let scheme = self.parse_word(line, "HMAC-SHA256 or AWS_SIGV4 after SIGN")?;
+
+        let scheme = if scheme.eq_ignore_ascii_case("HMAC-SHA256") {
+            let secret = self.parse_word(line, "secret after HMAC-SHA256")?;
+            let mut header = DEFAULT_SIGNATURE_HEADER.to_string();
+            let mut timestamp_header = DEFAULT_TIMESTAMP_HEADER.to_string();
+            let mut canonical = DEFAULT_SIGNATURE_TEMPLATE.to_string();
+            while self.on_line(line) {
+                if self.eat_keyword("HEADER").is_some() {
+                    header = self.parse_word(line, "header name after HEADER")?;
+                } else if self.eat_keyword("TIMESTAMP").is_some() {
+                    timestamp_header = self.parse_word(line, "header name after TIMESTAMP")?;
+                } else if self.eat_keyword("OVER").is_some() {
+                    canonical = self.parse_word(line, "template after OVER")?;
+                } else {
+                    return Err(self.unexpected());
+                }
+            }
+            SignScheme::HmacSha256 {
+                secret,
+                header,
+                timestamp_header,
+                canonical,
+            }
+        } else if scheme.eq_ignore_ascii_case("AWS_SIGV4") {
+            let service = self.parse_word(line, "service after AWS_SIGV4")?;
+            let region = self.parse_word(line, "region after the service")?;
+            let (mut access_key, mut secret_key, mut session_token) = (None, None, None);
+            if self.on_line(line) && self.eat_keyword("KEY").is_some() {
+                access_key = Some(self.parse_word(line, "access key id after KEY")?);
+                self.expect_keyword("SECRET")?;
+                secret_key = Some(self.parse_word(line, "secret key after SECRET")?);
+                if self.on_line(line) && self.eat_keyword("TOKEN").is_some() {
+                    session_token = Some(self.parse_word(line, "session token after TOKEN")?);
+                }
+            }
+            SignScheme::AwsSigV4 {
+                service,
+                region,
+                access_key,
+                secret_key,
+                session_token,
+            }
+        } else {
+            return Err(self.error(
+                &token,
+                ErrorCodes::Expected("HMAC-SHA256 or AWS_SIGV4 after SIGN".to_string()),
+            ));
+        };
+
+        Ok(Sign {
+            scheme,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // One value on `line`: a quoted string, or touching tokens up to the next
+    // space, as in `{token}` or `https://auth.test/token`
+    fn parse_word(&mut self, line: usize, what: &str) -> Result<String, ParseError> {
+        if !self.on_line(line) {
+            return Err(self.error(self.peek(), ErrorCodes::Expected(what.to_string())));
+        }
+        let first = self.advance();
+        if let TokenKind::Str(value) = first.kind {
+            return Ok(value);
+        }
+        let mut end = first.span.end;
+        while self.on_line(line) && self.peek().span.start == end {
+            end = self.advance().span.end;
+        }
+        Ok(self.source[first.span.start..end].to_string())
+    }
+
+    // `500ms`, `5s` or `2m`; a bare number is seconds
+    fn parse_duration_ms(&mut self, line: usize) -> Result<u64, ParseError> {
+        let token = self.peek().clone();
+        let invalid = |parser: &Self, span: Span| {
+            parser.error(
+                &token,
+                ErrorCodes::InvalidTimeoutValue(parser.source[span.start..span.end].to_string()),
+            )
+        };
+        let TokenKind::Number(n) = token.kind else {
+            return Err(invalid(self, token.span));
+        };
+        if !self.on_line(line) || n <= 0.0 {
+            return Err(invalid(self, token.span));
+        }
+        self.advance();
+
+        // The unit must touch the number, as in `5s`
+        let unit = match &self.peek().kind {
+            TokenKind::Ident(unit) if self.peek().span.start == token.span.end => {
+                Some(unit.to_ascii_lowercase())
+            }
+            _ => None,
+        };
+        let scale = match unit.as_deref() {
+            None | Some("s") => 1000.0,
+            Some("ms") => 1.0,
+            Some("m") => 60_000.0,
+            Some(_) => return Err(invalid(self, token.span.to(self.peek().span))),
+        };
+        if unit.is_some() {
+            self.advance();
+        }
+        Ok((n * scale).round() as u64)
+    }
+
+    // A comma-separated list of statuses such as `404` and ranges such as `200..299`
+    fn parse_statuses(&mut self) -> Result<Vec<StatusRange>, ParseError> {
+        let mut ranges = Vec::new();
+        loop {
+            let first = self.peek().clone();
+            let start = self.parse_status()?;
+            let end = if self.eat(&TokenKind::Dot).is_some() {
+                self.expect(&TokenKind::Dot, "'..' in a status range")?;
+                self.parse_status()?
+            } else {
+                start
+            };
+            if end < start {
+                let span = first.span.to(self.prev_span());
+                return Err(self.error(
+                    &first,
+                    ErrorCodes::InvalidStatus(self.source[span.start..span.end].to_string()),
+                ));
+            }
+            ranges.push(StatusRange { start, end });
+
+            if self.eat(&TokenKind::Comma).is_none() {
+                return Ok(ranges);
+            }
+        }
+    }
+
+    fn parse_status(&mut self) -> Result<u16, ParseError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(n) if n.fract() == 0.0 && (100.0..=599.0).contains(&n) => {
+                Ok(n as u16)
+            }
+            _ => Err(self.error(
+                &token,
+                ErrorCodes::InvalidStatus(self.text(&token).to_string()),
+            )),
+        }
+    }
+
+    // A query parameter name, bare or quoted as in `"page[number]"`
+    fn parse_param_name(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::Str(name) = &self.peek().kind {
+            let name = name.clone();
+            self.advance();
+            return Ok(name);
+        }
+        Ok(self.expect_ident("query parameter name")?.0)
+    }
+
+    // A header line starts with a name such as `X-Client` written without
+    // spaces and directly followed by `:`.
+    fn at_header_line(&self) -> bool {
+        if self.at_block_start() {
+            return false;
+        }
+
+        let line = self.peek().span.line;
+        let mut prev_end = self.peek().span.start;
+        let mut n = 0;
+        loop {
+            let token = self.peek_nth(n);
+            if token.kind == TokenKind::Eof || token.span.line != line {
+                return false;
+            }
+            if token.kind == TokenKind::Colon {
+                return n > 0;
+            }
+            if token.span.start != prev_end {
+                return false;
+            }
+            prev_end = token.span.end;
+            n += 1;
+        }
+    }
+
+    fn parse_header(&mut self) -> Result<Header, ParseError> {
+        let (key, value, span) = self.parse_pair("header")?;
+        Ok(Header { key, value, span })
+    }
+
+    fn parse_param(&mut self, what: &str) -> Result<Param, ParseError> {
+        let (key, value, span) = self.parse_pair(what)?;
+        Ok(Param { key, value, span })
+    }
+
+    // `key: value` on one line, as in headers, query parameters and form fields
+    fn parse_pair(&mut self, what: &str) -> Result<(String, String, Span), ParseError> {
+        let first = self.peek().clone();
+        let line = first.span.line;
+
+        let mut key_end = first.span.start;
+        while self.peek().kind != TokenKind::Colon {
+            if !self.on_line(line) {
+                return Err(self.error(
+                    self.peek(),
+                    ErrorCodes::Expected(format!("':' after {} name", what)),
+                ));
+            }
+            key_end = self.advance().span.end;
+        }
+
+        let key = self.source[first.span.start..key_end].trim().to_string();
+        if key.is_empty() {
+            return Err(self.error(&first, ErrorCodes::Expected(format!("{} name", what))));
+        }
+
+        let colon = self.advance();
+        let (value, value_span) = self
+            .rest_of_line(line)
+            .unwrap_or((String::new(), colon.span));
+
+        Ok((key, value, first.span.to(value_span)))
+    }
+
+    // --- RESPONSE BLOCK ---
+    fn parse_response(&mut self) -> Result<ResponseBlock, ParseError> {
+        let start = self.expect_keyword("RESPONSE")?.span;
+        let request = self.parse_response_binding(start.line);
+
+        let mut from_clause: Option<FromClause> = None;
+        let mut where_clause: Option<Expression> = None;
+        let mut group_by: Vec<Expression> = Vec::new();
+        let mut having: Option<Expression> = None;
+        let mut select_clause: Option<SelectClause> = None;
+        let mut distinct: Option<Distinct> = None;
+        let mut order_by: Vec<OrderByItem> = Vec::new();
+        let mut limit: Option<u32> = None;
+        let mut offset: Option<u32> = None;
+
+        while !self.at_block_start() {
+            if self.eat_keyword("FROM").is_some() {
+                from_clause = Some(self.parse_from()?);
+            } else if self.eat_keyword("WHERE").is_some() {
+                where_clause = Some(self.parse_non_aggregate_expr()?);
+            } else if self.eat_keyword("GROUP").is_some() {
+                self.expect_keyword("BY")?;
+                group_by = vec![self.parse_non_aggregate_expr()?];
+                while self.eat(&TokenKind::Comma).is_some() {
+                    group_by.push(self.parse_non_aggregate_expr()?);
+                }
+            } else if self.eat_keyword("HAVING").is_some() {
+                having = Some(self.parse_expr()?);
+            } else if self.eat_keyword("ORDER").is_some() {
+                self.expect_keyword("BY")?;
+                order_by = self.parse_order_by()?;
+            } else if self.eat_keyword("SELECT").is_some() {
+                distinct = self.parse_distinct()?;
+                select_clause = Some(self.parse_select()?);
+            } else if self.eat_keyword("LIMIT").is_some() {
+                limit = Some(self.parse_count(ErrorCodes::InvalidLimitValue)?);
+            } else if self.eat_keyword("OFFSET").is_some() {
+                offset = Some(self.parse_count(ErrorCodes::InvalidOffsetValue)?);
+            } else {
+                return Err(self.unexpected());
+            }
+        }
+
+        let span = start.to(self.prev_span());
+        let query = QueryBlock {
+            select_clause: select_clause.unwrap_or(SelectClause::Fields(Vec::new())),
+            distinct,
+            from_clause: from_clause.unwrap_or(FromClause {
+                request: None,
+                from_type: FromType::Body,
+                path: Vec::new(),
+                alias: None,
+                joins: Vec::new(),
+                unnest: Vec::new(),
+                span: start,
+            }),
+            where_clause,
+            group_by,
+            having,
+            order_by,
+            limit,
+            offset,
+            span,
+        };
+
+        Ok(ResponseBlock {
+            request,
+            query,
+            span,
+        })
+    }
+
+    // Non-negative integer argument of LIMIT and OFFSET
+    fn parse_count(&mut self, invalid: fn(String) -> ErrorCodes) -> Result<u32, ParseError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(n) if n >= 0.0 && n.fract() == 0.0 && n <= u32::MAX as f64 => {
+                Ok(n as u32)
+            }
+            _ => Err(self.error(&token, invalid(self.text(&token).to_string()))),
+        }
+    }
+
+    // `RESPONSE GetUsers` or `RESPONSE FROM GetUsers` on the RESPONSE line. A FROM
+    // followed by more than a lone name, or by body/response, is the data clause.
+    fn parse_response_binding(&mut self, line: usize) -> Option<RequestRef> {
+        let offset = usize::from(self.at_keyword("FROM"));
+        let name = self.peek_nth(offset);
+        let TokenKind::Ident(text) = &name.kind else {
+            return None;
+        };
+        if name.span.line != line || is_reserved(text) {
+            return None;
+        }
+
+        let next = self.peek_nth(offset + 1);
+        if offset == 1
+            && (text == "body"
+                || text == "response"
+                || (next.span.line == line && next.kind != TokenKind::Eof))
+        {
+            return None;
+        }
+
+        let request = RequestRef {
+            name: text.clone(),
+            span: name.span,
+        };
+        for _ in 0..=offset {
+            self.advance();
+        }
+        Some(request)
+    }
+
+    fn parse_from(&mut self) -> Result<FromClause, ParseError> {
+        let (request, from_type, path, start) = self.parse_source()?;
+
+        let alias = if self.eat_keyword("AS").is_some() {
+            Some(self.expect_ident("alias after AS")?.0)
+        } else {
+            None
+        };
+
+        let mut joins = Vec::new();
+        while self.at_join() {
+            joins.push(self.parse_join()?);
+        }
+
+        let mut unnest = Vec::new();
+        while self.at_keyword("UNNEST") || self.at_keyword("OUTER") {
+            let start = self.peek().span;
+            let outer = self.eat_keyword("OUTER").is_some();
+            self.expect_keyword("UNNEST")?;
+            let path = self.parse_field_path()?;
+            self.expect_keyword("AS")?;
+            let (alias, alias_span) = self.expect_ident("alias after AS")?;
+            unnest.push(Unnest {
+                path,
+                alias,
+                outer,
+                span: start.to(alias_span),
+            });
+        }
+
+        Ok(FromClause {
+            request,
+            from_type,
+            path,
+            alias,
+            joins,
+            unnest,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    // body.x, response.x, Name.body.x or Name.response.x; anything else falls back to body
+    fn parse_source(
+        &mut self,
+    ) -> Result<(Option<String>, FromType, Vec<PathSegment>, Span), ParseError> {
+        // A bare `response` is the envelope itself, though RESPONSE is reserved
+        if self.at_keyword("RESPONSE") && self.peek_nth(1).kind != TokenKind::Dot {
+            let span = self.advance().span;
+            return Ok((None, FromType::Response, Vec::new(), span));
+        }
+        let path = self.parse_field_path()?;
+        let (request, rest) = match path.path.as_slice() {
+            [PathSegment::Key(name), PathSegment::Key(root), ..]
+                if self.request_names.contains(name) && (root == "body" || root == "response") =>
+            {
+                (Some(name.clone()), &path.path[1..])
+            }
+            _ => (None, path.path.as_slice()),
+        };
+
+        let (from_type, rest) = match rest.first() {
+            Some(PathSegment::Key(root)) if root == "body" => (FromType::Body, rest[1..].to_vec()),
+            Some(PathSegment::Key(root)) if root == "response" => {
+                (FromType::Response, rest[1..].to_vec())
+            }
+            // Fallback to body
+            _ => (FromType::Body, rest.to_vec()),
+        };
+
+        Ok((request, from_type, rest, path.span))
     }
-}
-
-pub fn is_identifier_like(s: &str) -> bool {
-    // Match plain identifiers (name, field_name, etc.) or handlebars-style variables ({varname})
-    let re = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*|\{[A-Za-z_][A-Za-z0-9_]*\})$").unwrap();
-    re.is_match(s)
-}
 
-fn find_top_level_logical(tokens: &[String]) -> Option<(usize, String)> {
-    for (i, t) in tokens.iter().enumerate() {
-        let tu = t.to_uppercase();
-        if tu == "AND" || tu == "OR" {
-            return Some((i, tu));
-        }
+    fn at_join(&self) -> bool {
+        self.at_keyword("JOIN")
+            || ((self.at_keyword("INNER") || self.at_keyword("LEFT"))
+                && (self.peek_nth(1).is_keyword("JOIN") || self.peek_nth(1).is_keyword("OUTER")))
     }
 
-    None
-}
+    fn parse_join(&mut self) -> Result<Join, ParseError> {
+        let start = self.peek().span;
+        let kind = if self.eat_keyword("LEFT").is_some() {
+            self.eat_keyword("OUTER");
+            JoinKind::Left
+        } else {
+            self.eat_keyword("INNER");
+            JoinKind::Inner
+        };
+        self.expect_keyword("JOIN")?;
 
-// --- PARSER FUNCTIONS ---
-pub fn parse_program(input: &str) -> Result<Program, ParseError> {
-    let mut using_block: Option<UsingBlock> = None;
-    let mut request_blocks: Vec<RequestBlock> = Vec::new();
-    let mut response_blocks: Vec<ResponseBlock> = Vec::new();
+        let (request, from_type, path, _) = self.parse_source()?;
+        self.expect_keyword("AS")?;
+        let (alias, _) = self.expect_ident("alias after AS")?;
+        self.expect_keyword("ON")?;
+        let on = self.parse_non_aggregate_expr()?;
 
-    let mut lines: Vec<String> = input.lines().map(|l| l.to_string()).collect();
+        Ok(Join {
+            kind,
+            request,
+            from_type,
+            path,
+            alias,
+            span: start.to(on.span()),
+            on,
+        })
+    }
 
-    // Clean Comments
-    for l in lines.iter_mut() {
-        if l.is_empty() {
-            continue;
+    fn parse_distinct(&mut self) -> Result<Option<Distinct>, ParseError> {
+        if self.eat_keyword("DISTINCT").is_none() {
+            return Ok(None);
         }
-        if let Some(index) = l.find("#") {
-            l.truncate(index);
+        if self.eat_keyword("ON").is_none() {
+            return Ok(Some(Distinct::Rows));
         }
-        *l = l.trim().to_string();
-    }
 
-    let mut i = 0;
-    while i < lines.len() {
-        let line = lines[i].trim();
-        if line.is_empty() {
-            i += 1;
-            continue;
+        self.expect(&TokenKind::LParen, "'(' after DISTINCT ON")?;
+        let mut keys = vec![self.parse_expr()?];
+        while self.eat(&TokenKind::Comma).is_some() {
+            keys.push(self.parse_expr()?);
         }
+        self.expect(&TokenKind::RParen, "',' or ')'")?;
+        Ok(Some(Distinct::On(keys)))
+    }
 
-        // --- Parse USING Block ---
-        if line.eq_ignore_ascii_case("USING") {
-            i += 1;
-            let mut vars = Vec::new();
-            while i < lines.len() {
-                let l = lines[i].trim();
-                if l.is_empty() {
-                    i += 1;
-                    break;
-                }
+    fn parse_select(&mut self) -> Result<SelectClause, ParseError> {
+        // Simple Fields
+        if self.eat(&TokenKind::LBrace).is_none() {
+            let mut fields = vec![self.parse_field_path()?];
+            while self.eat(&TokenKind::Comma).is_some() {
+                fields.push(self.parse_field_path()?);
+            }
+            return Ok(SelectClause::Fields(fields));
+        }
 
-                if is_toplevel_keyword(l) {
-                    break;
+        // Object fields, possibly spanning several lines
+        let mut fields: Vec<SelectField> = Vec::new();
+        while self.eat(&TokenKind::RBrace).is_none() {
+            let field = if matches!(self.peek_nth(1).kind, TokenKind::Dot | TokenKind::LBracket) {
+                // `o.id` is shorthand for `id: o.id`
+                let path = self.parse_field_path()?;
+                SelectField {
+                    alias: path.name().unwrap_or_default().to_string(),
+                    span: path.span,
+                    expression: Some(Expression::FieldPathExpr(path)),
                 }
-
-                if let Some(colon) = l.find(':') {
-                    let name = l[..colon].trim().to_string();
-                    let value = trim_quotes(l[colon + 1..].trim());
-                    vars.push(VarDeclaration { name, value });
+            } else {
+                let (alias, alias_span) = self.expect_ident("field name")?;
+                if self.eat(&TokenKind::Colon).is_some() {
+                    let expression = self.parse_expr()?;
+                    SelectField {
+                        alias,
+                        span: alias_span.to(expression.span()),
+                        expression: Some(expression),
+                    }
                 } else {
-                    return Err(ParseError::Syntax {
-                        line: i + 1,
-                        column: 1,
-                        message: ErrorCodes::UnexpectedToken(l.to_string()),
-                    });
+                    SelectField {
+                        alias,
+                        expression: None,
+                        span: alias_span,
+                    }
                 }
+            };
+            fields.push(field);
+
+            if self.eat(&TokenKind::Comma).is_none() {
+                self.expect(&TokenKind::RBrace, "',' or '}'")?;
+                break;
+            }
+        }
+
+        Ok(SelectClause::Objects(fields))
+    }
+
+    fn parse_order_by(&mut self) -> Result<Vec<OrderByItem>, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            let expression = self.parse_expr()?;
+
+            let mut direction = SortDirection::Asc;
+            if self.eat_keyword("DESC").is_some() {
+                direction = SortDirection::Desc;
+            } else {
+                self.eat_keyword("ASC");
+            }
 
-                i += 1;
+            let mut nulls = None;
+            if self.eat_keyword("NULLS").is_some() {
+                let token = self.advance();
+                nulls = Some(if token.is_keyword("FIRST") {
+                    NullsOrder::First
+                } else if token.is_keyword("LAST") {
+                    NullsOrder::Last
+                } else {
+                    return Err(self.error(
+                        &token,
+                        ErrorCodes::InvalidOrderBy(self.text(&token).to_string()),
+                    ));
+                });
             }
 
-            using_block = Some(UsingBlock {
-                var_declarations: vars,
+            items.push(OrderByItem {
+                span: expression.span().to(self.prev_span()),
+                expression,
+                direction,
+                nulls,
             });
-            continue;
-
-        // --- Parse REQUEST Block ---
-        } else if line.to_uppercase().starts_with("REQUEST ") {
-            // Extract Request Name
-            let request_name = line["REQUEST ".len()..].trim().to_string();
-            i += 1;
-
-            let mut method = HttpMethods::Get;
-            let mut url = String::new();
-            let mut headers: Vec<Header> = Vec::new();
-            let mut cache = CacheDuration::None;
-
-            while i < lines.len() {
-                let l = lines[i].trim();
-                if l.is_empty() {
-                    i += 1;
-                    break;
-                }
 
-                if is_toplevel_keyword(l) {
-                    break;
-                }
+            if self.eat(&TokenKind::Comma).is_none() {
+                break;
+            }
+        }
+        Ok(items)
+    }
 
-                // Parse Method
-                let upper = l.to_uppercase();
-                if upper.starts_with("GET ")
-                    || upper.starts_with("DELETE ")
-                    || upper.starts_with("PATCH ")
-                    || upper.starts_with("PUT ")
-                    || upper.starts_with("POST ")
-                {
-                    let parts: Vec<&str> = l.split_whitespace().collect();
-                    method = match parts[0].to_uppercase().as_str() {
-                        "GET" => HttpMethods::Get,
-                        "POST" => HttpMethods::Post,
-                        "PUT" => HttpMethods::Put,
-                        "DELETE" => HttpMethods::Delete,
-                        "PATCH" => HttpMethods::Patch,
-                        _ => HttpMethods::Get,
-                    };
-                    url = l[parts[0].len()..].trim().to_string();
+    // a.b, "odd key".c, tags[0], items[-1], items[*].price, items.*, ..id
+    fn parse_field_path(&mut self) -> Result<FieldPath, ParseError> {
+        let start = self.peek().span;
+        let mut path = Vec::new();
+        if self.eat(&TokenKind::Dot).is_some() {
+            self.expect(&TokenKind::Dot, "'..' before a field name")?;
+            let (key, _) = self.parse_path_segment("field name after '..'")?;
+            path.push(PathSegment::Descendant(key));
+        } else {
+            // A bare keyword ends the expression, but `order.id` is still a path
+            if let TokenKind::Ident(name) = &self.peek().kind
+                && is_reserved(name)
+                && self.peek_nth(1).kind != TokenKind::Dot
+            {
+                return Err(self.unexpected());
+            }
+            let (key, _) = self.parse_path_segment("field name")?;
+            path.push(PathSegment::Key(key));
+        }
+
+        loop {
+            // `tags [0]` is not an index, so brackets must touch the previous segment
+            if self.peek().kind == TokenKind::LBracket
+                && self.peek().span.start == self.prev_span().end
+            {
+                path.push(self.parse_index()?);
+            } else if self.eat(&TokenKind::Dot).is_some() {
+                if self.eat(&TokenKind::Dot).is_some() {
+                    let (key, _) = self.parse_path_segment("field name after '..'")?;
+                    path.push(PathSegment::Descendant(key));
+                } else if self.eat(&TokenKind::Star).is_some() {
+                    path.push(PathSegment::Wildcard);
+                } else {
+                    let after_headers =
+                        matches!(path.last(), Some(PathSegment::Key(k)) if k == "headers");
+                    let (mut key, _) = self.parse_path_segment("field name after '.'")?;
+                    if after_headers {
+                        self.extend_header_name(&mut key);
+                    }
+                    path.push(PathSegment::Key(key));
                 }
-                // Parse Headers
-                else if upper.starts_with("HEADER ") {
-                    i += 1;
-                    while i < lines.len() {
-                        let hline = lines[i].trim();
-                        if hline.is_empty() || is_toplevel_keyword(hline) {
-                            break;
-                        }
+            } else {
+                break;
+            }
+        }
 
-                        if let Some(colon) = hline.find(':') {
-                            let key = hline[..colon].trim().to_string();
-                            let value = trim_quotes(hline[colon + 1..].trim());
-                            headers.push(Header { key, value });
-                        } else {
-                            break;
-                        }
+        Ok(FieldPath {
+            path,
+            span: start.to(self.prev_span()),
+        })
+    }
 
-                        i += 1;
-                    }
-                    continue;
+    // Header names such as `x-ratelimit-remaining` after `headers.`, written
+    // without spaces around the dashes
+    fn extend_header_name(&mut self, key: &mut String) {
+        while self.peek().kind == TokenKind::Minus
+            && self.peek().span.start == self.prev_span().end
+            && self.peek_nth(1).span.start == self.peek().span.end
+            && matches!(
+                self.peek_nth(1).kind,
+                TokenKind::Ident(_) | TokenKind::Number(_)
+            )
+        {
+            self.advance();
+            let part = self.advance();
+            key.push('-');
+            key.push_str(self.text(&part));
+        }
+    }
+
+    fn parse_index(&mut self) -> Result<PathSegment, ParseError> {
+        self.expect(&TokenKind::LBracket, "'['")?;
+        let segment = if self.eat(&TokenKind::Star).is_some() {
+            PathSegment::Wildcard
+        } else {
+            let negative = self.eat(&TokenKind::Minus).is_some();
+            let token = self.peek().clone();
+            match token.kind {
+                TokenKind::Number(n) if n.fract() == 0.0 && n <= i64::MAX as f64 => {
+                    self.advance();
+                    PathSegment::Index(if negative { -(n as i64) } else { n as i64 })
                 }
-                // Parse Cache
-                else if upper.starts_with("CACHE ") {
-                    if let Some(num_s) = l.split_whitespace().nth(1) {
-                        if let Ok(n) = num_s.parse::<u64>() {
-                            cache = CacheDuration::DurationInSeconds(n);
-                        }
-                    }
+                _ => {
+                    return Err(self.error(
+                        &token,
+                        ErrorCodes::Expected("array index or '*'".to_string()),
+                    ));
                 }
+            }
+        };
+        self.expect(&TokenKind::RBracket, "']'")?;
+        Ok(segment)
+    }
+
+    fn parse_path_segment(&mut self, what: &str) -> Result<(String, Span), ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Str(name) => {
+                self.advance();
+                Ok((name, token.span))
+            }
+            _ => self.expect_ident(what),
+        }
+    }
+
+    // --- EXPRESSIONS ---
+    // WHERE and GROUP BY run per row, before any aggregation
+    fn parse_non_aggregate_expr(&mut self) -> Result<Expression, ParseError> {
+        let expr = self.parse_expr()?;
+        self.reject_aggregate(&expr)?;
+        Ok(expr)
+    }
+
+    fn reject_aggregate(&self, expr: &Expression) -> Result<(), ParseError> {
+        match expr.aggregate_span() {
+            Some(span) => Err(ParseError::Syntax {
+                span,
+                message: ErrorCodes::MisplacedAggregate(
+                    self.source[span.start..span.end].to_string(),
+                ),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Precedence climbing; binary operators are left-associative.
+    fn parse_expr(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(0)
+    }
+
+    fn peek_binary_op(&self) -> Option<(BinaryOp, u8)> {
+        let token = self.peek();
+        if token.is_keyword("OR") {
+            return Some((BinaryOp::Or, PREC_OR));
+        }
+        if token.is_keyword("AND") {
+            return Some((BinaryOp::And, PREC_AND));
+        }
+
+        let op = match token.kind {
+            TokenKind::Gte => (BinaryOp::Gte, PREC_COMPARISON),
+            TokenKind::Lte => (BinaryOp::Lte, PREC_COMPARISON),
+            TokenKind::Gt => (BinaryOp::Gt, PREC_COMPARISON),
+            TokenKind::Lt => (BinaryOp::Lt, PREC_COMPARISON),
+            TokenKind::Neq => (BinaryOp::Neq, PREC_COMPARISON),
+            TokenKind::RegexMatch => (BinaryOp::RegexMatch, PREC_COMPARISON),
+            TokenKind::EqEq | TokenKind::Eq => (BinaryOp::Eq, PREC_COMPARISON),
+            TokenKind::Concat => (BinaryOp::Concat, PREC_CONCAT),
+            TokenKind::Plus => (BinaryOp::Add, PREC_ADDITIVE),
+            TokenKind::Minus => (BinaryOp::Sub, PREC_ADDITIVE),
+            TokenKind::Star => (BinaryOp::Mul, PREC_MULTIPLICATIVE),
+            TokenKind::Slash => (BinaryOp::Div, PREC_MULTIPLICATIVE),
+            TokenKind::Percent => (BinaryOp::Mod, PREC_MULTIPLICATIVE),
+            _ => return None,
+        };
+        Some(op)
+    }
 
-                i += 1;
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expression, ParseError> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            // IN, BETWEEN, LIKE and IS NULL bind like comparisons
+            if min_prec <= PREC_COMPARISON && self.at_predicate() {
+                left = self.parse_predicate(left)?;
+                continue;
+            }
+
+            let Some((op, prec)) = self.peek_binary_op() else {
+                break;
+            };
+            if prec < min_prec {
+                break;
             }
+            self.advance();
+
+            let right = self.parse_binary(prec + 1)?;
+            left = Expression::BinaryOpExpr {
+                span: left.span().to(right.span()),
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn at_predicate(&self) -> bool {
+        let is_predicate =
+            |token: &Token| NEGATABLE_PREDICATES.iter().any(|kw| token.is_keyword(kw));
+        let token = self.peek();
+        token.is_keyword("IS")
+            || is_predicate(token)
+            || (token.is_keyword("NOT") && is_predicate(self.peek_nth(1)))
+    }
+
+    fn parse_predicate(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let expr = Box::new(left);
 
-            request_blocks.push(RequestBlock {
-                name: request_name,
-                method,
-                url,
-                headers,
-                cache,
+        if self.eat_keyword("IS").is_some() {
+            let negated = self.eat_keyword("NOT").is_some();
+            let null = self.expect_keyword("NULL")?;
+            return Ok(Expression::IsNullExpr {
+                span: expr.span().to(null.span),
+                expr,
+                negated,
             });
-            continue;
-        }
-        // --- Parse RESPONSE Block ---
-        else if line.eq_ignore_ascii_case("RESPONSE") {
-            i += 1;
-
-            let mut from_clause: Option<FromClause> = None;
-            let mut where_clause: Option<Expression> = None;
-            let mut select_clause: Option<SelectClause> = None;
-            let mut limit: Option<u32> = None;
-
-            while i < lines.len() {
-                let l = lines[i].trim();
-                // Skip empty lines and comments within RESPONSE block
-                if l.is_empty() || l.starts_with('#') {
-                    i += 1;
-                    continue;
-                }
+        }
 
-                if is_toplevel_keyword(l) {
-                    break;
-                }
+        let negated = self.eat_keyword("NOT").is_some();
+        // Operands bind tighter than the predicate, so `a BETWEEN 1 AND 2 AND b` splits as expected
+        let operand_prec = PREC_COMPARISON + 1;
 
-                if l.to_uppercase().starts_with("FROM ") {
-                    let rest = l["FROM ".len()..].trim();
+        if self.eat_keyword("IN").is_some() {
+            let list = if self.peek().kind == TokenKind::LParen {
+                self.parse_array(TokenKind::RParen, "',' or ')'")?
+            } else {
+                self.parse_binary(operand_prec)?
+            };
+            return Ok(Expression::InExpr {
+                span: expr.span().to(list.span()),
+                expr,
+                list: Box::new(list),
+                negated,
+            });
+        }
 
-                    //body
-                    if rest.starts_with("body.") {
-                        let path: Vec<String> = rest["body.".len()..]
-                            .split('.')
-                            .map(|s| s.to_string())
-                            .collect();
+        if self.eat_keyword("BETWEEN").is_some() {
+            let low = self.parse_binary(operand_prec)?;
+            self.expect_keyword("AND")?;
+            let high = self.parse_binary(operand_prec)?;
+            return Ok(Expression::BetweenExpr {
+                span: expr.span().to(high.span()),
+                expr,
+                low: Box::new(low),
+                high: Box::new(high),
+                negated,
+            });
+        }
 
-                        from_clause = Some(FromClause {
-                            from_type: FromType::Body,
-                            path,
-                        });
-                    } else if rest.starts_with("response.") {
-                        let path: Vec<String> = rest["response.".len()..]
-                            .split('.')
-                            .map(|s| s.to_string())
-                            .collect();
-
-                        from_clause = Some(FromClause {
-                            from_type: FromType::Response,
-                            path,
-                        });
-                    } else {
-                        // Fallback to body
-                        let path: Vec<String> = rest.split('.').map(|s| s.to_string()).collect();
-                        from_clause = Some(FromClause {
-                            from_type: FromType::Body,
-                            path,
-                        });
-                    }
-                } else if l.to_uppercase().starts_with("WHERE ") {
-                    let expr_text = l["WHERE".len()..].trim();
-                    where_clause = Some(parse_expression(expr_text)?);
-                } else if l.to_uppercase().starts_with("ORDER BY ") {
-                    // TODO: Implement ORDER BY parsing
-                } else if l.to_uppercase().starts_with("SELECT ") {
-                    // Check if it's object select
-                    if l.contains('{') {
-                        let mut select_text = String::new();
-                        if l.contains('}') {
-                            // Inline Select
-                            if let Some(start) = l.find('{') {
-                                if let Some(end) = l.find('}') {
-                                    select_text = l[start + 1..end].trim().to_string();
-                                }
-                            }
-                        } else {
-                            // Different lines
-                            if let Some(start) = l.find('{') {
-                                select_text.push_str(&l[start + 1..]);
-                            }
-                            i += 1;
-                            while i < lines.len() {
-                                let s = lines[i].trim();
-                                if let Some(end) = s.find('}') {
-                                    select_text.push_str(" ");
-                                    select_text.push_str(&s[..end]);
-                                    break;
-                                } else {
-                                    select_text.push_str(" ");
-                                    select_text.push_str(s);
-                                }
-                                i += 1;
-                            }
-                        }
+        let case_insensitive = if self.eat_keyword("ILIKE").is_some() {
+            true
+        } else {
+            self.expect_keyword("LIKE")?;
+            false
+        };
+        let pattern = self.parse_binary(operand_prec)?;
+        Ok(Expression::LikeExpr {
+            span: expr.span().to(pattern.span()),
+            expr,
+            pattern: Box::new(pattern),
+            case_insensitive,
+            negated,
+        })
+    }
 
-                        // Parse select fields
-                        let parts: Vec<&str> = select_text
-                            .split(',')
-                            .map(|p| p.trim())
-                            .filter(|p| !p.is_empty())
-                            .collect();
-                        let mut fields: Vec<SelectField> = Vec::new();
-                        for p in parts {
-                            // Preicate
-                            if let Some(colon) = p.find(':') {
-                                let alias = p[..colon].trim().to_string();
-                                let expr_str = p[colon + 1..].trim();
-                                let expression = parse_expression(expr_str)?;
-                                fields.push(SelectField {
-                                    alias,
-                                    expression: Some(expression),
-                                });
-                            } else {
-                                fields.push(SelectField {
-                                    alias: p.to_string(),
-                                    expression: None,
-                                });
-                            }
-                        }
-                        select_clause = Some(SelectClause::Objects(fields));
-                    } else {
-                        // Simple Fields
-                        let fields_text = l["SELECT ".len()..].trim();
-                        let fields: Vec<String> = fields_text
-                            .split(',')
-                            .map(|f| f.trim().to_string())
-                            .collect();
-                        select_clause = Some(SelectClause::Fields(fields));
-                    }
-                } else if l.to_uppercase().starts_with("LIMIT ") {
-                    if let Some(num_s) = l.split_whitespace().nth(1) {
-                        if let Ok(n) = num_s.parse::<u32>() {
-                            limit = Some(n);
-                        } else {
-                            return Err(ParseError::Syntax {
-                                line: i + 1,
-                                column: 1,
-                                message: ErrorCodes::InvalidLimitValue(num_s.to_string()),
-                            });
-                        }
-                    }
+    // Comma-separated expressions between the current opening token and `close`
+    fn parse_array(&mut self, close: TokenKind, what: &str) -> Result<Expression, ParseError> {
+        let open = self.advance();
+        let mut items = Vec::new();
+        if self.peek().kind != close {
+            items.push(self.parse_expr()?);
+            while self.eat(&TokenKind::Comma).is_some() {
+                items.push(self.parse_expr()?);
+            }
+        }
+        let end = self.expect(&close, what)?;
+        Ok(Expression::ArrayExpr {
+            items,
+            span: open.span.to(end.span),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let token = self.peek().clone();
+
+        // NOT binds looser than comparisons: `NOT a = b` is `NOT (a = b)`
+        if token.is_keyword("NOT") || token.kind == TokenKind::Bang {
+            self.advance();
+            let operand = self.parse_binary(PREC_NOT)?;
+            return Ok(Expression::UnaryOpExpr {
+                span: token.span.to(operand.span()),
+                op: UnaryOp::Not,
+                operand: Box::new(operand),
+            });
+        }
+
+        if token.kind == TokenKind::Minus {
+            self.advance();
+            let operand = self.parse_unary()?;
+            let span = token.span.to(operand.span());
+            // Fold negative number literals
+            if let Expression::LiteralExpr(Literal::NumberLiteral(n), _) = operand {
+                return Ok(Expression::LiteralExpr(Literal::NumberLiteral(-n), span));
+            }
+            return Ok(Expression::UnaryOpExpr {
+                span,
+                op: UnaryOp::Neg,
+                operand: Box::new(operand),
+            });
+        }
+
+        self.parse_term()
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, ParseError> {
+        let token = self.peek().clone();
+        match &token.kind {
+            TokenKind::Str(s) => {
+                self.advance();
+                Ok(Expression::LiteralExpr(
+                    Literal::StringLiteral(s.clone()),
+                    token.span,
+                ))
+            }
+            TokenKind::Number(n) => {
+                self.advance();
+                Ok(Expression::LiteralExpr(
+                    Literal::NumberLiteral(*n),
+                    token.span,
+                ))
+            }
+            TokenKind::LParen => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect(&TokenKind::RParen, "')'")?;
+                Ok(expr)
+            }
+            TokenKind::LBracket => self.parse_array(TokenKind::RBracket, "',' or ']'"),
+            TokenKind::Dot => Ok(Expression::FieldPathExpr(self.parse_field_path()?)),
+            // {var} is kept as a placeholder string and resolved by unroll_handlers
+            TokenKind::LBrace => {
+                self.advance();
+                let (name, _) = self.expect_ident("variable name")?;
+                let close = self.expect(&TokenKind::RBrace, "'}' after variable name")?;
+                Ok(Expression::LiteralExpr(
+                    Literal::StringLiteral(format!("{{{}}}", name)),
+                    token.span.to(close.span),
+                ))
+            }
+            TokenKind::Ident(name) => {
+                if name.eq_ignore_ascii_case("true") || name.eq_ignore_ascii_case("false") {
+                    self.advance();
+                    let b = name.eq_ignore_ascii_case("true");
+                    return Ok(Expression::LiteralExpr(
+                        Literal::BooleanLiteral(b),
+                        token.span,
+                    ));
+                }
+                if name.eq_ignore_ascii_case("case") {
+                    return self.parse_case();
                 }
-                i += 1;
+                if name.eq_ignore_ascii_case("null") {
+                    self.advance();
+                    return Ok(Expression::LiteralExpr(Literal::Null, token.span));
+                }
+                if self.peek_nth(1).kind == TokenKind::LParen {
+                    return self.parse_call();
+                }
+                Ok(Expression::FieldPathExpr(self.parse_field_path()?))
             }
+            _ => Err(self.unexpected()),
+        }
+    }
 
-            let q = QueryBlock {
-                select_clause: select_clause.unwrap_or(SelectClause::Fields(Vec::new())),
-                from_clause: from_clause.unwrap_or(FromClause {
-                    from_type: FromType::Body,
-                    path: Vec::new(),
-                }),
-                where_clause,
-                limit,
-            };
+    // CASE [operand] WHEN .. THEN .. [WHEN ..] [ELSE ..] END, free to span lines
+    fn parse_case(&mut self) -> Result<Expression, ParseError> {
+        let start = self.expect_keyword("CASE")?.span;
+        let operand = if self.at_keyword("WHEN") {
+            None
+        } else {
+            Some(Box::new(self.parse_expr()?))
+        };
 
-            response_blocks.push(ResponseBlock { query: q });
+        let mut branches = Vec::new();
+        while self.eat_keyword("WHEN").is_some() {
+            let when = self.parse_expr()?;
+            self.expect_keyword("THEN")?;
+            let then = self.parse_expr()?;
+            branches.push(CaseBranch { when, then });
+        }
+        if branches.is_empty() {
+            return Err(self.error(self.peek(), ErrorCodes::Expected("WHEN".to_string())));
+        }
 
-            continue;
+        let else_result = if self.eat_keyword("ELSE").is_some() {
+            Some(Box::new(self.parse_expr()?))
         } else {
-            //TODO: Handle unexpected lines
-            i += 1;
+            None
+        };
+        let end = self.expect_keyword("END")?;
+
+        Ok(Expression::Case {
+            operand,
+            branches,
+            else_result,
+            span: start.to(end.span),
+        })
+    }
+
+    // Aggregates such as COUNT(*) or SUM(price), and scalar built-ins such as lower(name)
+    fn parse_call(&mut self) -> Result<Expression, ParseError> {
+        let name_token = self.peek().clone();
+        let (name, start) = self.expect_ident("function name")?;
+        self.expect(&TokenKind::LParen, "'('")?;
+
+        if let Some(func) = AggregateFunc::from_name(&name) {
+            return self.parse_aggregate_args(func, start);
+        }
+
+        let Some(func) = lookup_function(&name) else {
+            return Err(self.error(&name_token, ErrorCodes::UnknownFunction(name)));
+        };
+
+        let mut args = Vec::new();
+        if self.peek().kind != TokenKind::RParen {
+            args.push(self.parse_expr()?);
+            while self.eat(&TokenKind::Comma).is_some() {
+                args.push(self.parse_expr()?);
+            }
         }
+        let close = self.expect(&TokenKind::RParen, "',' or ')'")?;
+
+        if !func.accepts(args.len()) {
+            return Err(self.error(
+                &name_token,
+                ErrorCodes::WrongArgumentCount(format!(
+                    "{}() takes {} argument(s), got {}",
+                    func.name,
+                    func.arity(),
+                    args.len()
+                )),
+            ));
+        }
+
+        Ok(Expression::FunctionCall {
+            name: func.name.to_string(),
+            args,
+            span: start.to(close.span),
+        })
     }
 
-    let mut program = Program {
-        using_block,
-        request_blocks,
-        response_blocks,
-    };
+    fn parse_aggregate_args(
+        &mut self,
+        func: AggregateFunc,
+        start: Span,
+    ) -> Result<Expression, ParseError> {
+        let arg = if func == AggregateFunc::Count && self.eat(&TokenKind::Star).is_some() {
+            None
+        } else {
+            let arg = self.parse_expr()?;
+            // Aggregates cannot be nested
+            self.reject_aggregate(&arg)?;
+            Some(Box::new(arg))
+        };
+
+        let close = self.expect(&TokenKind::RParen, "')'")?;
+        Ok(Expression::Aggregate {
+            func,
+            arg,
+            span: start.to(close.span),
+        })
+    }
+}
 
+// --- PARSER FUNCTIONS ---
+pub fn parse_program(input: &str) -> Result<Program, ParseError> {
+    let mut program = Parser::new(input)?.parse_program()?;
     unroll_handlers(&mut program);
     Ok(program)
 }
 
+pub fn parse_expression(s: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser::new(s)?;
+    let expr = parser.parse_expr()?;
+    if !parser.at_eof() {
+        return Err(parser.unexpected());
+    }
+    Ok(expr)
+}
+
 /// --- VARIABLE UNROLLING ---
 pub fn unroll_handlers(program: &mut Program) {
     let mut vars: HashMap<String, String> = HashMap::new();
@@ -359,25 +1780,17 @@ pub fn unroll_handlers(program: &mut Program) {
     }
 
     // Helper Regex for Handlebars variable replacement
-    let hb_regex = Regex::new(r"\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
-    let replace_vars = |text: &str| -> String {
-        let result = hb_regex.replace_all(text, |caps: &regex::Captures| {
-            let var_name = &caps[1];
-            if let Some(value) = vars.get(var_name) {
-                value.clone()
-            } else {
-                caps[0].to_string()
-            }
-        });
-        result.to_string()
+    let hb_regex = Regex::new(r"\{\+?([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let replace_vars = |text: &str, escape: Escape| {
+        replace_placeholders(text, &hb_regex, escape, |caps| {
+            Ok::<_, Infallible>(vars.get(&caps[1]).cloned())
+        })
     };
 
     // Replace in Request Blocks
     for req in program.request_blocks.iter_mut() {
-        req.url = replace_vars(&req.url);
-        for header in req.headers.iter_mut() {
-            header.value = replace_vars(&header.value);
-        }
+        req.map_texts(replace_vars)
+            .unwrap_or_else(|never| match never {});
     }
 
     // Replace in Response Blocks
@@ -387,17 +1800,32 @@ pub fn unroll_handlers(program: &mut Program) {
             resolve_expr(expr, &vars);
         }
 
-        match &mut resp.query.select_clause {
-            SelectClause::Fields(fields) => {
-                for f in fields.iter_mut() {
-                    *f = replace_vars(f);
-                }
+        for expr in resp.query.group_by.iter_mut() {
+            resolve_expr(expr, &vars);
+        }
+
+        if let Some(expr) = &mut resp.query.having {
+            resolve_expr(expr, &vars);
+        }
+
+        for item in resp.query.order_by.iter_mut() {
+            resolve_expr(&mut item.expression, &vars);
+        }
+
+        for join in resp.query.from_clause.joins.iter_mut() {
+            resolve_expr(&mut join.on, &vars);
+        }
+
+        if let Some(Distinct::On(keys)) = &mut resp.query.distinct {
+            for expr in keys.iter_mut() {
+                resolve_expr(expr, &vars);
             }
-            SelectClause::Objects(obj_fields) => {
-                for of in obj_fields.iter_mut() {
-                    if let Some(expr) = &mut of.expression {
-                        resolve_expr(expr, &vars);
-                    }
+        }
+
+        if let SelectClause::Objects(obj_fields) = &mut resp.query.select_clause {
+            for of in obj_fields.iter_mut() {
+                if let Some(expr) = &mut of.expression {
+                    resolve_expr(expr, &vars);
                 }
             }
         }
@@ -406,7 +1834,7 @@ pub fn unroll_handlers(program: &mut Program) {
 
 pub fn resolve_expr(expr: &mut Expression, vars: &HashMap<String, String>) {
     match expr {
-        Expression::LiteralExpr(lit) => {
+        Expression::LiteralExpr(lit, _) => {
             if let Literal::StringLiteral(s) = lit {
                 let re = Regex::new(r"^\{([A-Za-z_][A-Za-z0-9_]*)\}$").unwrap();
                 if let Some(caps) = re.captures(s) {
@@ -436,163 +1864,50 @@ pub fn resolve_expr(expr: &mut Expression, vars: &HashMap<String, String>) {
             }
         }
         Expression::FieldPathExpr(_) => {}
-        Expression::BinaryOpExpr { left, right, .. } => {
-            resolve_expr(left, vars);
-            resolve_expr(right, vars);
-        }
-    }
-}
-
-// --- EXPRESSION PARSING ---
-fn tokenize_expr(s: &str) -> Vec<String> {
-    let s = s.trim();
-    let re = Regex::new(r"(>=|<=|!=|==|=~|>|<|=|\band\b|\bAND\b|\bor\b|\bOR\b)").unwrap();
-    let mut out = Vec::new();
-    let mut last = 0usize;
-
-    for m in re.find_iter(s) {
-        if m.start() > last {
-            out.push(s[last..m.start()].trim().to_string());
+        Expression::UnaryOpExpr { operand, .. } => resolve_expr(operand, vars),
+        Expression::Aggregate { arg, .. } => {
+            if let Some(arg) = arg {
+                resolve_expr(arg, vars);
+            }
         }
-
-        out.push(s[m.start()..m.end()].trim().to_string());
-        last = m.end();
-    }
-
-    if last < s.len() {
-        out.push(s[last..].trim().to_string());
-    }
-
-    //Split by whitespace
-    out.into_iter()
-        .flat_map(|chunk| {
-            if chunk.contains(' ') {
-                chunk.split_whitespace().map(|s| s.to_string()).collect()
-            } else {
-                vec![chunk]
+        Expression::FunctionCall { args, .. } | Expression::ArrayExpr { items: args, .. } => {
+            for arg in args.iter_mut() {
+                resolve_expr(arg, vars);
             }
-        })
-        .collect()
-}
-
-pub fn parse_expression(s: &str) -> Result<Expression, ParseError> {
-    let tokens: Vec<String> = tokenize_expr(s);
-
-    if tokens.is_empty() {
-        return Err(ParseError::Syntax {
-            line: 0,
-            column: 0,
-            message: ErrorCodes::UnexpectedToken(s.to_string()),
-        });
-    }
-
-    if let Some((pos, op)) = find_top_level_logical(&tokens) {
-        let left = tokens[..pos].join(" ");
-        let right = tokens[pos + 1..].join(" ");
-        let left_expr = parse_expression(&left)?;
-        let right_expr = parse_expression(&right)?;
-
-        let binop = match op.as_str() {
-            "AND" => BinaryOp::And,
-            "OR" => BinaryOp::Or,
-            _ => {
-                return Err(ParseError::Syntax {
-                    line: 0,
-                    column: 0,
-                    message: ErrorCodes::UnexpectedToken(op),
-                });
+        }
+        Expression::InExpr { expr, list, .. } => {
+            resolve_expr(expr, vars);
+            resolve_expr(list, vars);
+        }
+        Expression::BetweenExpr {
+            expr, low, high, ..
+        } => {
+            resolve_expr(expr, vars);
+            resolve_expr(low, vars);
+            resolve_expr(high, vars);
+        }
+        Expression::LikeExpr { expr, pattern, .. } => {
+            resolve_expr(expr, vars);
+            resolve_expr(pattern, vars);
+        }
+        Expression::IsNullExpr { expr, .. } => resolve_expr(expr, vars),
+        Expression::Case {
+            operand,
+            branches,
+            else_result,
+            ..
+        } => {
+            for expr in operand.iter_mut().chain(else_result.iter_mut()) {
+                resolve_expr(expr, vars);
             }
-        };
-
-        return Ok(Expression::BinaryOpExpr {
-            left: Box::new(left_expr),
-            op: binop,
-            right: Box::new(right_expr),
-        });
-    }
-
-    let mut op_pos: Option<(usize, &str)> = None;
-    for (idx, t) in tokens.iter().enumerate() {
-        let up = t.to_uppercase();
-        match up.as_str() {
-            ">=" | "<=" | "!=" | "==" | "=~" | ">" | "<" | "=" => {
-                op_pos = Some((idx, t.as_str()));
-                break;
+            for branch in branches.iter_mut() {
+                resolve_expr(&mut branch.when, vars);
+                resolve_expr(&mut branch.then, vars);
             }
-            _ => {}
+        }
+        Expression::BinaryOpExpr { left, right, .. } => {
+            resolve_expr(left, vars);
+            resolve_expr(right, vars);
         }
     }
-
-    if let Some((pos, op_token)) = op_pos {
-        let left = tokens[..pos].join(" ");
-        let right = tokens[pos + 1..].join(" ");
-        let left_expr = parse_term(&left)?;
-        let right_expr = parse_term(&right)?;
-        let binop = match op_token {
-            ">=" => BinaryOp::Gte,
-            "<=" => BinaryOp::Lte,
-            ">" => BinaryOp::Gt,
-            "<" => BinaryOp::Lt,
-            "!=" => BinaryOp::Neq,
-            "=~" => BinaryOp::RegexMatch,
-            "==" | "=" => BinaryOp::Eq,
-            _ => BinaryOp::Eq,
-        };
-        return Ok(Expression::BinaryOpExpr {
-            left: Box::new(left_expr),
-            op: binop,
-            right: Box::new(right_expr),
-        });
-    }
-    parse_term(&s)
-}
-
-fn parse_term(s: &str) -> Result<Expression, ParseError> {
-    let t = s.trim();
-    if t.is_empty() {
-        return Err(ParseError::Syntax {
-            line: 0,
-            column: 0,
-            message: ErrorCodes::UnexpectedToken(s.to_string()),
-        });
-    }
-
-    if is_identifier_like(t) || t.contains(".") {
-        let path_parts: Vec<String> = t.split('.').map(|p| p.trim().to_string()).collect();
-        return Ok(Expression::FieldPathExpr(FieldPath { path: path_parts }));
-    }
-
-    if (t.starts_with('"') && t.ends_with('"')) || (t.starts_with('\'') && t.ends_with('\'')) {
-        let content = trim_quotes(t);
-        return Ok(Expression::LiteralExpr(Literal::StringLiteral(content)));
-    }
-
-    // boolean
-    if t.eq_ignore_ascii_case("true") {
-        return Ok(Expression::LiteralExpr(Literal::BooleanLiteral(true)));
-    }
-
-    if t.eq_ignore_ascii_case("false") {
-        return Ok(Expression::LiteralExpr(Literal::BooleanLiteral(false)));
-    }
-
-    // number
-    if let Ok(n) = t.parse::<f64>() {
-        return Ok(Expression::LiteralExpr(Literal::NumberLiteral(n)));
-    }
-
-    let re = Regex::new(r"^\{([A-Za-z_][A-Za-z0-9_]*)\}$").unwrap();
-    if let Some(caps) = re.captures(t) {
-        let name = caps.get(1).unwrap().as_str().to_string();
-        // represent as StringLiteral for now; will be resolved & converted later
-        return Ok(Expression::LiteralExpr(Literal::StringLiteral(format!(
-            "{{{}}}",
-            name
-        ))));
-    }
-
-    // fallback treat as string literal
-    Ok(Expression::LiteralExpr(Literal::StringLiteral(
-        t.to_string(),
-    )))
 }
@@ -1,175 +1,1067 @@
 use crate::ast::*;
 use crate::errors::QueryError;
+use crate::request::replace_placeholders;
 use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 
+// Responses of the program's requests by name, each an envelope as built by
+// `response_envelope`, for `FROM Name.body`, `FROM Name.response` and JOIN
+pub type Sources = HashMap<String, Value>;
+
+// What `FROM response` reads: {status, headers, body, elapsed_ms, url}. Header
+// names are lowercased and repeated headers joined with ", ".
+pub fn response_envelope(
+    url: &str,
+    status: u16,
+    headers: &[(String, String)],
+    body: Value,
+    elapsed_ms: u64,
+) -> Value {
+    let mut header_map = serde_json::Map::new();
+    for (key, value) in headers {
+        match header_map.entry(key.to_ascii_lowercase()) {
+            serde_json::map::Entry::Occupied(mut e) => {
+                let joined = format!("{}, {}", e.get().as_str().unwrap_or_default(), value);
+                e.insert(Value::String(joined));
+            }
+            serde_json::map::Entry::Vacant(e) => {
+                e.insert(Value::String(value.clone()));
+            }
+        }
+    }
+
+    let mut envelope = serde_json::Map::new();
+    envelope.insert("status".to_string(), Value::from(status));
+    envelope.insert("headers".to_string(), Value::Object(header_map));
+    envelope.insert("body".to_string(), body);
+    envelope.insert("elapsed_ms".to_string(), Value::from(elapsed_ms));
+    envelope.insert("url".to_string(), Value::from(url));
+    Value::Object(envelope)
+}
+
+// Runs a query over a bare body, with no status or headers to read
 pub fn execute_query(query: &QueryBlock, body: &Value) -> Result<Value, QueryError> {
-    let root = match query.from_clause.from_type {
-        FromType::Body => body,
-        FromType::Response => {
-            return Err(QueryError::TypeError {
-                message: "Response from_type not supported yet".to_string(),
-            });
+    let mut envelope = serde_json::Map::new();
+    envelope.insert("body".to_string(), body.clone());
+    execute_query_with_sources(query, &Value::Object(envelope), &Sources::new())
+}
+
+// `response` is the envelope of the query's own request
+pub fn execute_query_with_sources(
+    query: &QueryBlock,
+    response: &Value,
+    sources: &Sources,
+) -> Result<Value, QueryError> {
+    let from = &query.from_clause;
+    let rows = source_rows(
+        from.request.as_deref(),
+        &from.from_type,
+        &from.path,
+        response,
+        sources,
+    )?;
+    let rows = expand_rows(rows, from, response, sources)?;
+
+    // Apply WHERE clause
+    let mut filtered: Vec<Value> = Vec::with_capacity(rows.len());
+    for row in rows {
+        if let Some(cond) = &query.where_clause
+            && !eval_filter(cond, Scope::row(&row, response))?
+        {
+            continue;
+        }
+        filtered.push(row);
+    }
+
+    // Apply GROUP BY and HAVING clauses
+    let empty_row = Value::Object(serde_json::Map::new());
+    let groups: Vec<Vec<Value>>;
+    let scopes: Vec<Scope> = if is_aggregate_query(query) {
+        groups = group_rows(&filtered, &query.group_by, &query.select_clause, response)?;
+        let mut scopes = Vec::with_capacity(groups.len());
+        for group in &groups {
+            let scope = Scope {
+                row: group.first().unwrap_or(&empty_row),
+                group: Some(group),
+                response,
+            };
+            if let Some(having) = &query.having
+                && !eval_bool_expr(having, scope)?
+            {
+                continue;
+            }
+            scopes.push(scope);
         }
+        scopes
+    } else {
+        filtered
+            .iter()
+            .map(|row| Scope::row(row, response))
+            .collect()
     };
 
-    let from_val = resolve_path(root, &query.from_clause.path)?;
-    let rows = match from_val {
-        Value::Array(arr) => arr.clone(),
-        other => vec![other.clone()],
+    // Apply ORDER BY clause
+    let sorted = if query.order_by.is_empty() {
+        scopes
+    } else {
+        sort_scopes(scopes, &query.order_by, &query.select_clause)?
     };
 
-    // Apply WHERE clause
-    let filtered: Vec<Value> = rows
-        .into_iter()
-        .filter(|row| {
-            if let Some(cond) = &query.where_clause {
-                eval_bool_expr(cond, row).unwrap_or(false)
-            } else {
-                true
-            }
-        })
-        .collect();
+    // Apply DISTINCT ON, keeping the first row of each key in sorted order
+    let sorted = match &query.distinct {
+        Some(Distinct::On(keys)) => distinct_on(sorted, keys, &query.select_clause)?,
+        _ => sorted,
+    };
 
     // Apply SELECT clause
     let mapped: Vec<Value> = match &query.select_clause {
-        SelectClause::Fields(fields) => filtered
+        SelectClause::Fields(fields) => sorted
             .into_iter()
-            .map(|row| project_fields(&row, fields))
+            .map(|scope| project_fields(scope.row, fields))
             .collect::<Result<Vec<_>, _>>()?,
-        SelectClause::Objects(select_fields) => filtered
+        SelectClause::Objects(select_fields) => sorted
             .into_iter()
-            .map(|row| project_object_fields(&row, select_fields))
+            .map(|scope| project_object_fields(scope, select_fields))
             .collect::<Result<Vec<_>, _>>()?,
     };
 
-    // Apply LIMIT clause
-    let limited = if let Some(limit) = query.limit {
-        mapped.into_iter().take(limit as usize).collect()
-    } else {
-        mapped
+    // Apply DISTINCT on the projected rows
+    let mapped = match &query.distinct {
+        Some(Distinct::Rows) => {
+            let mut seen = HashSet::new();
+            mapped
+                .into_iter()
+                .filter(|row| seen.insert(canonical_json(row)))
+                .collect()
+        }
+        _ => mapped,
+    };
+
+    // Apply OFFSET and LIMIT clauses, after sorting so pages are stable
+    let paged = mapped
+        .into_iter()
+        .skip(query.offset.unwrap_or(0) as usize)
+        .take(query.limit.map_or(usize::MAX, |limit| limit as usize))
+        .collect();
+
+    Ok(Value::Array(paged))
+}
+
+fn source_rows(
+    request: Option<&str>,
+    from_type: &FromType,
+    path: &[PathSegment],
+    response: &Value,
+    sources: &Sources,
+) -> Result<Vec<Value>, QueryError> {
+    let response = match request {
+        Some(name) => sources.get(name).ok_or_else(|| QueryError::MissingSource {
+            request: name.to_string(),
+        })?,
+        None => response,
+    };
+    let root = match from_type {
+        FromType::Body => &response["body"],
+        FromType::Response => response,
+    };
+
+    Ok(match resolve_path(root, path)? {
+        Value::Array(arr) => arr,
+        other => vec![other],
+    })
+}
+
+// Applies the FROM alias, JOIN and UNNEST clauses. Every alias becomes a key on
+// the row object next to the parent's own fields, so `id`, `order.id` and
+// `item.sku` all resolve.
+fn expand_rows(
+    rows: Vec<Value>,
+    from: &FromClause,
+    response: &Value,
+    sources: &Sources,
+) -> Result<Vec<Value>, QueryError> {
+    let mut rows = match &from.alias {
+        Some(alias) => rows
+            .into_iter()
+            .map(|row| with_alias(&row, alias, row.clone()))
+            .collect(),
+        None => rows,
+    };
+
+    for join in &from.joins {
+        let right = source_rows(
+            join.request.as_deref(),
+            &join.from_type,
+            &join.path,
+            response,
+            sources,
+        )?;
+        rows = join_rows(rows, join, right, response)?;
+    }
+
+    for unnest in &from.unnest {
+        let mut expanded = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let items = match resolve_path(row, &unnest.path.path) {
+                Ok(Value::Array(items)) => items,
+                Ok(Value::Null) | Err(QueryError::MissingField { .. }) => Vec::new(),
+                Ok(other) => vec![other],
+                Err(e) => return Err(e),
+            };
+
+            if items.is_empty() && unnest.outer {
+                expanded.push(with_alias(row, &unnest.alias, Value::Null));
+            }
+            for item in items {
+                expanded.push(with_alias(row, &unnest.alias, item));
+            }
+        }
+        rows = expanded;
+    }
+
+    Ok(rows)
+}
+
+// Hash join on the equalities in ON that compare a left-side expression with
+// one over the joined alias; any other condition is checked per matched pair.
+// Without such an equality every pair is tried.
+fn join_rows(
+    rows: Vec<Value>,
+    join: &Join,
+    right: Vec<Value>,
+    response: &Value,
+) -> Result<Vec<Value>, QueryError> {
+    let mut left_keys = Vec::new();
+    let mut right_keys = Vec::new();
+    let mut residual = Vec::new();
+    for term in conjuncts(&join.on) {
+        if let Expression::BinaryOpExpr {
+            left,
+            op: BinaryOp::Eq,
+            right,
+            ..
+        } = term
+        {
+            match (join_side(left, &join.alias), join_side(right, &join.alias)) {
+                (JoinSide::Left, JoinSide::Right) => {
+                    left_keys.push(left.as_ref());
+                    right_keys.push(right.as_ref());
+                    continue;
+                }
+                (JoinSide::Right, JoinSide::Left) => {
+                    left_keys.push(right.as_ref());
+                    right_keys.push(left.as_ref());
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        residual.push(term);
+    }
+
+    // Right rows are evaluated as `{ alias: row }` so `u.id` resolves
+    let empty = Value::Object(serde_json::Map::new());
+    let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, row) in right.iter().enumerate() {
+        let scoped = with_alias(&empty, &join.alias, row.clone());
+        if let Some(key) = join_key(&right_keys, &scoped, response)? {
+            index.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut joined = Vec::with_capacity(rows.len());
+    for left in &rows {
+        let candidates = match join_key(&left_keys, left, response)? {
+            Some(key) => index.get(&key).map(Vec::as_slice).unwrap_or_default(),
+            None => &[],
+        };
+
+        let mut matched = false;
+        for &i in candidates {
+            let row = with_alias(left, &join.alias, right[i].clone());
+            let mut keep = true;
+            for cond in &residual {
+                if !eval_filter(cond, Scope::row(&row, response))? {
+                    keep = false;
+                    break;
+                }
+            }
+            if keep {
+                joined.push(row);
+                matched = true;
+            }
+        }
+        if !matched && join.kind == JoinKind::Left {
+            joined.push(with_alias(left, &join.alias, Value::Null));
+        }
+    }
+    Ok(joined)
+}
+
+// NULL keys never match, as in SQL
+fn join_key(
+    exprs: &[&Expression],
+    row: &Value,
+    response: &Value,
+) -> Result<Option<String>, QueryError> {
+    let mut key = Vec::with_capacity(exprs.len());
+    for expr in exprs {
+        let value = eval_or_null(expr, Scope::row(row, response))?;
+        if value.is_null() {
+            return Ok(None);
+        }
+        key.push(value);
+    }
+    Ok(Some(canonical_json(&Value::Array(key))))
+}
+
+fn conjuncts(expr: &Expression) -> Vec<&Expression> {
+    match expr {
+        Expression::BinaryOpExpr {
+            left,
+            op: BinaryOp::And,
+            right,
+            ..
+        } => {
+            let mut terms = conjuncts(left);
+            terms.extend(conjuncts(right));
+            terms
+        }
+        other => vec![other],
+    }
+}
+
+enum JoinSide {
+    Left,
+    Right,
+    Both,
+}
+
+// Which side of a join an expression reads from; constants count as left
+fn join_side(expr: &Expression, alias: &str) -> JoinSide {
+    fn visit(expr: &Expression, alias: &str, left: &mut bool, right: &mut bool) {
+        if let Expression::FieldPathExpr(fp) = expr {
+            match fp.path.first() {
+                Some(PathSegment::Key(root)) if root == alias => *right = true,
+                _ => *left = true,
+            }
+        }
+        for child in expr.children() {
+            visit(child, alias, left, right);
+        }
+    }
+
+    let (mut left, mut right) = (false, false);
+    visit(expr, alias, &mut left, &mut right);
+    match (left, right) {
+        (_, false) => JoinSide::Left,
+        (false, true) => JoinSide::Right,
+        (true, true) => JoinSide::Both,
+    }
+}
+
+fn with_alias(row: &Value, alias: &str, value: Value) -> Value {
+    let mut obj = match row {
+        Value::Object(map) => map.clone(),
+        _ => serde_json::Map::new(),
     };
+    obj.insert(alias.to_string(), value);
+    Value::Object(obj)
+}
 
-    Ok(Value::Array(limited))
+// A row to evaluate against; aggregate queries also carry every row of the group.
+// `response.status` and friends fall back to the request's envelope.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    row: &'a Value,
+    group: Option<&'a [Value]>,
+    response: &'a Value,
+}
+
+impl<'a> Scope<'a> {
+    fn row(row: &'a Value, response: &'a Value) -> Self {
+        Scope {
+            row,
+            group: None,
+            response,
+        }
+    }
+}
+
+pub(crate) fn is_aggregate_query(query: &QueryBlock) -> bool {
+    let select_has_aggregate = match &query.select_clause {
+        SelectClause::Fields(_) => false,
+        SelectClause::Objects(fields) => fields
+            .iter()
+            .filter_map(|f| f.expression.as_ref())
+            .any(Expression::contains_aggregate),
+    };
+
+    !query.group_by.is_empty()
+        || query.having.is_some()
+        || select_has_aggregate
+        || query
+            .order_by
+            .iter()
+            .any(|item| item.expression.contains_aggregate())
+}
+
+// Groups keep the order in which their first row appeared. Without GROUP BY
+// the whole input, even when empty, is a single group.
+fn group_rows(
+    rows: &[Value],
+    group_by: &[Expression],
+    select: &SelectClause,
+    response: &Value,
+) -> Result<Vec<Vec<Value>>, QueryError> {
+    if group_by.is_empty() {
+        return Ok(vec![rows.to_vec()]);
+    }
+
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<Vec<Value>> = Vec::new();
+    for row in rows {
+        let mut key = Vec::with_capacity(group_by.len());
+        for expr in group_by {
+            let expr = resolve_select_alias(expr, select);
+            key.push(eval_or_null(expr, Scope::row(row, response))?);
+        }
+
+        match index.entry(canonical_json(&Value::Array(key))) {
+            Entry::Occupied(e) => groups[*e.get()].push(row.clone()),
+            Entry::Vacant(e) => {
+                e.insert(groups.len());
+                groups.push(vec![row.clone()]);
+            }
+        }
+    }
+    Ok(groups)
+}
+
+fn distinct_on<'a>(
+    scopes: Vec<Scope<'a>>,
+    keys: &[Expression],
+    select: &SelectClause,
+) -> Result<Vec<Scope<'a>>, QueryError> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::new();
+    for scope in scopes {
+        let mut key = Vec::with_capacity(keys.len());
+        for expr in keys {
+            let expr = resolve_select_alias(expr, select);
+            key.push(eval_or_null(expr, scope)?);
+        }
+        if seen.insert(canonical_json(&Value::Array(key))) {
+            kept.push(scope);
+        }
+    }
+    Ok(kept)
+}
+
+// Key-order independent JSON text where numerically equal numbers (1 and 1.0)
+// print the same, so it can serve as a hash key for equality.
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+                (f as i64).to_string()
+            }
+            _ => n.to_string(),
+        },
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let entries: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical_json(v)))
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+// Stable sort on precomputed keys so every expression is evaluated once per row
+fn sort_scopes<'a>(
+    scopes: Vec<Scope<'a>>,
+    order_by: &[OrderByItem],
+    select: &SelectClause,
+) -> Result<Vec<Scope<'a>>, QueryError> {
+    let mut keyed: Vec<(Vec<Value>, Scope)> = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        let mut keys = Vec::with_capacity(order_by.len());
+        for item in order_by {
+            let expr = resolve_select_alias(&item.expression, select);
+            keys.push(eval_or_null(expr, scope)?);
+        }
+        keyed.push((keys, scope));
+    }
+
+    keyed.sort_by(|(a, _), (b, _)| {
+        for (item, (ka, kb)) in order_by.iter().zip(a.iter().zip(b.iter())) {
+            let ord = compare_sort_keys(ka, kb, item);
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    });
+
+    Ok(keyed.into_iter().map(|(_, scope)| scope).collect())
+}
+
+// ORDER BY and GROUP BY may name a computed SELECT alias instead of a source field
+fn resolve_select_alias<'a>(expr: &'a Expression, select: &'a SelectClause) -> &'a Expression {
+    if let (Expression::FieldPathExpr(fp), SelectClause::Objects(fields)) = (expr, select)
+        && let [PathSegment::Key(name)] = fp.path.as_slice()
+        && let Some(aliased) = fields
+            .iter()
+            .find(|f| &f.alias == name)
+            .and_then(|f| f.expression.as_ref())
+    {
+        return aliased;
+    }
+    expr
+}
+
+// Rows without the field behave like NULL
+fn eval_or_null(expr: &Expression, scope: Scope) -> Result<Value, QueryError> {
+    match eval_expr(expr, scope) {
+        Err(QueryError::MissingField { .. }) => Ok(Value::Null),
+        other => other,
+    }
+}
+
+fn compare_sort_keys(a: &Value, b: &Value, item: &OrderByItem) -> Ordering {
+    let nulls_first = match item.nulls {
+        Some(NullsOrder::First) => true,
+        Some(NullsOrder::Last) => false,
+        None => item.direction == SortDirection::Desc,
+    };
+
+    match (a.is_null(), b.is_null()) {
+        (true, true) => Ordering::Equal,
+        (true, false) if nulls_first => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (false, true) if nulls_first => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => {
+            let ord = compare_values(a, b);
+            match item.direction {
+                SortDirection::Asc => ord,
+                SortDirection::Desc => ord.reverse(),
+            }
+        }
+    }
+}
+
+// Total order over JSON values: null < bool < number < string < array < object
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5,
+        }
+    }
+
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => {
+            let x = x.as_f64().unwrap_or(f64::NAN);
+            let y = y.as_f64().unwrap_or(f64::NAN);
+            x.total_cmp(&y)
+        }
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => {
+            for (xi, yi) in x.iter().zip(y.iter()) {
+                let ord = compare_values(xi, yi);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (Value::Object(_), Value::Object(_)) => a.to_string().cmp(&b.to_string()),
+        _ => rank(a).cmp(&rank(b)),
+    }
 }
 
 // Given a vector path, resolve it or throw
-fn resolve_path<'a>(value: &'a Value, path: &[String]) -> Result<&'a Value, QueryError> {
-    let mut current = value;
+// Paths stay on a single value until a wildcard or `..` fans out
+enum Resolved<'a> {
+    One(&'a Value),
+    Many(Vec<&'a Value>),
+}
+
+static NULL: Value = Value::Null;
+
+pub(crate) fn resolve_path(value: &Value, path: &[PathSegment]) -> Result<Value, QueryError> {
+    let mut current = Resolved::One(value);
     for segment in path {
-        match current {
-            Value::Object(map) => {
-                current = map.get(segment).ok_or(QueryError::MissingField {
-                    field: segment.clone(),
-                })?;
+        current = match (current, segment) {
+            // NULL propagates through field and index access, e.g. from OUTER UNNEST
+            (Resolved::One(Value::Null), PathSegment::Key(_) | PathSegment::Index(_)) => {
+                Resolved::One(&NULL)
             }
-            _ => {
-                return Err(QueryError::TypeError {
-                    message: format!("cannot access field `{}` on non-object", segment),
-                });
+            (Resolved::One(v), PathSegment::Key(key)) => match v {
+                Value::Object(map) => Resolved::One(
+                    map.get(key)
+                        .ok_or(QueryError::MissingField { field: key.clone() })?,
+                ),
+                _ => {
+                    return Err(QueryError::TypeError {
+                        message: format!("cannot access field `{}` on non-object", key),
+                    });
+                }
+            },
+            (Resolved::One(v), PathSegment::Index(i)) => match v {
+                // Out of range reads as NULL, like a missing element
+                Value::Array(items) => Resolved::One(array_index(items, *i).unwrap_or(&NULL)),
+                _ => {
+                    return Err(QueryError::ExpectedArray {
+                        path: segment.to_string(),
+                        found: json_type_name(v).to_string(),
+                    });
+                }
+            },
+            (Resolved::One(v), PathSegment::Wildcard) => Resolved::Many(children(v)),
+            (Resolved::One(v), PathSegment::Descendant(key)) => {
+                let mut found = Vec::new();
+                descendants(v, key, &mut found);
+                Resolved::Many(found)
             }
-        }
+
+            // Once fanned out, values the segment does not apply to are skipped
+            (Resolved::Many(values), PathSegment::Key(key)) => {
+                Resolved::Many(values.into_iter().filter_map(|v| v.get(key)).collect())
+            }
+            (Resolved::Many(values), PathSegment::Index(i)) => Resolved::Many(
+                values
+                    .into_iter()
+                    .filter_map(|v| v.as_array().and_then(|items| array_index(items, *i)))
+                    .collect(),
+            ),
+            (Resolved::Many(values), PathSegment::Wildcard) => {
+                Resolved::Many(values.into_iter().flat_map(children).collect())
+            }
+            (Resolved::Many(values), PathSegment::Descendant(key)) => {
+                let mut found = Vec::new();
+                for v in values {
+                    descendants(v, key, &mut found);
+                }
+                Resolved::Many(found)
+            }
+        };
     }
-    Ok(current)
+
+    Ok(match current {
+        Resolved::One(v) => v.clone(),
+        Resolved::Many(values) => Value::Array(values.into_iter().cloned().collect()),
+    })
 }
 
-// Select specific fields from a JSON object
-fn project_fields(row: &Value, fields: &[String]) -> Result<Value, QueryError> {
+fn array_index(items: &[Value], index: i64) -> Option<&Value> {
+    let index = if index < 0 {
+        items.len().checked_sub(index.unsigned_abs() as usize)?
+    } else {
+        index as usize
+    };
+    items.get(index)
+}
+
+fn children(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(map) => map.values().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Every value stored under `key`, outermost first, searching nested objects and arrays
+fn descendants<'a>(value: &'a Value, key: &str, found: &mut Vec<&'a Value>) {
+    if let Value::Object(map) = value
+        && let Some(v) = map.get(key)
+    {
+        found.push(v);
+    }
+    for child in children(value) {
+        descendants(child, key, found);
+    }
+}
+
+pub(crate) fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn project_fields(row: &Value, fields: &[FieldPath]) -> Result<Value, QueryError> {
     let mut obj = serde_json::Map::new();
     for field in fields {
-        let v = resolve_path(row, &[field.clone()])?;
-        obj.insert(field.clone(), v.clone());
+        let v = resolve_path(row, &field.path)?;
+        // Nested paths are keyed by their last field name
+        obj.insert(field.name().unwrap_or_default().to_string(), v);
     }
     Ok(Value::Object(obj))
 }
 
 // Select object fields with optional expressions and stuff
-fn project_object_fields(row: &Value, fields: &[SelectField]) -> Result<Value, QueryError> {
+fn project_object_fields(scope: Scope, fields: &[SelectField]) -> Result<Value, QueryError> {
     let mut obj = serde_json::Map::new();
     for field in fields {
         let value = if let Some(expr) = &field.expression {
             // Compute the expression
-            eval_expr(expr, row)?
+            eval_expr(expr, scope)?
         } else {
             // Just get the field from the row here
-            resolve_path(row, &[field.alias.clone()])?.clone()
+            resolve_path(scope.row, &[PathSegment::Key(field.alias.clone())])?
         };
         obj.insert(field.alias.clone(), value);
     }
     Ok(Value::Object(obj))
 }
 
-fn eval_bool_expr(expr: &Expression, row: &Value) -> Result<bool, QueryError> {
-    let v = eval_expr(expr, row)?;
+// A row without the field a condition reads is filtered out rather than
+// failing the query
+fn eval_filter(expr: &Expression, scope: Scope) -> Result<bool, QueryError> {
+    match eval_bool_expr(expr, scope) {
+        Err(QueryError::MissingField { .. }) => Ok(false),
+        other => other,
+    }
+}
+
+// NULL counts as false, as in SQL
+fn eval_bool_expr(expr: &Expression, scope: Scope) -> Result<bool, QueryError> {
+    let v = eval_expr(expr, scope)?;
     match v {
         Value::Bool(b) => Ok(b),
+        Value::Null => Ok(false),
         other => Err(QueryError::TypeError {
             message: format!("WHERE expression must be bool, got {:?}", other),
         }),
     }
 }
 
-fn eval_expr(expr: &Expression, row: &Value) -> Result<Value, QueryError> {
+fn eval_expr(expr: &Expression, scope: Scope) -> Result<Value, QueryError> {
     match expr {
-        Expression::LiteralExpr(Literal::NumberLiteral(n)) => {
-            let num = serde_json::Number::from_f64(*n).ok_or(QueryError::TypeError {
-                message: "Invalid number".to_string(),
-            })?;
-            Ok(Value::Number(num))
-        }
+        // Integral literals stay integers so `age == 18` and `1 + 2` behave
+        Expression::LiteralExpr(Literal::NumberLiteral(n), _) => integral_value(*n),
+
+        Expression::LiteralExpr(Literal::StringLiteral(s), _) => Ok(Value::String(s.clone())),
 
-        Expression::LiteralExpr(Literal::StringLiteral(s)) => Ok(Value::String(s.clone())),
+        Expression::LiteralExpr(Literal::BooleanLiteral(b), _) => Ok(Value::Bool(*b)),
 
-        Expression::LiteralExpr(Literal::BooleanLiteral(b)) => Ok(Value::Bool(*b)),
+        Expression::LiteralExpr(Literal::Null, _) => Ok(Value::Null),
 
-        Expression::LiteralExpr(Literal::Null) => Ok(Value::Null),
+        Expression::FieldPathExpr(fp) => match fp.path.split_first() {
+            Some((PathSegment::Key(root), rest))
+                if root == "response" && scope.row.get("response").is_none() =>
+            {
+                resolve_path(scope.response, rest)
+            }
+            _ => resolve_path(scope.row, &fp.path),
+        },
 
-        Expression::FieldPathExpr(fp) => resolve_path(row, &fp.path).cloned(),
+        Expression::UnaryOpExpr { op, operand, .. } => {
+            let v = eval_expr(operand, scope)?;
+            eval_unary(op, &v)
+        }
 
         // Recursive binary operation evaluation
-        Expression::BinaryOpExpr { left, op, right } => {
-            let l = eval_expr(left, row)?;
-            let r = eval_expr(right, row)?;
+        Expression::BinaryOpExpr {
+            left, op, right, ..
+        } => {
+            let l = eval_expr(left, scope)?;
+
+            // Short-circuit so the right side may rely on the left, e.g. `a != null AND a > 1`
+            match (op, l.as_bool()) {
+                (BinaryOp::And, Some(false)) => return Ok(Value::Bool(false)),
+                (BinaryOp::Or, Some(true)) => return Ok(Value::Bool(true)),
+                _ => {}
+            }
+
+            let r = eval_expr(right, scope)?;
             eval_binary(&l, op, &r)
         }
+
+        Expression::Aggregate { func, arg, .. } => {
+            let group = scope.group.ok_or(QueryError::TypeError {
+                message: "aggregate functions are only allowed in SELECT, HAVING and ORDER BY"
+                    .to_string(),
+            })?;
+            eval_aggregate(*func, arg.as_deref(), group, scope.response)
+        }
+
+        Expression::FunctionCall { name, args, .. } => {
+            let func = lookup_function(name).ok_or(QueryError::TypeError {
+                message: format!("unknown function {}", name),
+            })?;
+            let values = args
+                .iter()
+                .map(|arg| eval_or_null(arg, scope))
+                .collect::<Result<Vec<_>, _>>()?;
+            (func.eval)(&values)
+        }
+
+        Expression::ArrayExpr { items, .. } => items
+            .iter()
+            .map(|item| eval_expr(item, scope))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+
+        Expression::InExpr {
+            expr,
+            list,
+            negated,
+            ..
+        } => {
+            let value = eval_expr(expr, scope)?;
+            let list = eval_expr(list, scope)?;
+            eval_in(&value, &list, *negated)
+        }
+
+        Expression::BetweenExpr {
+            expr,
+            low,
+            high,
+            negated,
+            ..
+        } => {
+            let value = eval_expr(expr, scope)?;
+            let low = eval_expr(low, scope)?;
+            let high = eval_expr(high, scope)?;
+            eval_between(&value, &low, &high, *negated)
+        }
+
+        Expression::LikeExpr {
+            expr,
+            pattern,
+            case_insensitive,
+            negated,
+            ..
+        } => {
+            let value = eval_expr(expr, scope)?;
+            let pattern = eval_expr(pattern, scope)?;
+            eval_like(&value, &pattern, *case_insensitive, *negated)
+        }
+
+        // A missing field counts as NULL here
+        Expression::IsNullExpr { expr, negated, .. } => {
+            let is_null = eval_or_null(expr, scope)?.is_null();
+            Ok(Value::Bool(is_null != *negated))
+        }
+
+        Expression::Case {
+            operand,
+            branches,
+            else_result,
+            ..
+        } => {
+            let operand = operand.as_ref().map(|o| eval_expr(o, scope)).transpose()?;
+            for branch in branches {
+                let when = eval_expr(&branch.when, scope)?;
+                let matched = match &operand {
+                    // NULL never matches, as in SQL
+                    Some(value) => !value.is_null() && values_equal(value, &when),
+                    None => match when {
+                        Value::Bool(b) => b,
+                        Value::Null => false,
+                        other => {
+                            return Err(QueryError::TypeError {
+                                message: format!("CASE WHEN condition must be bool, got {}", other),
+                            });
+                        }
+                    },
+                };
+                if matched {
+                    return eval_expr(&branch.then, scope);
+                }
+            }
+            match else_result {
+                Some(expr) => eval_expr(expr, scope),
+                None => Ok(Value::Null),
+            }
+        }
+    }
+}
+
+// Whole numbers that fit in i64 become JSON integers
+fn integral_value(n: f64) -> Result<Value, QueryError> {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Ok(Value::from(n as i64))
+    } else {
+        number_value(n)
+    }
+}
+
+fn number_value(n: f64) -> Result<Value, QueryError> {
+    let num = serde_json::Number::from_f64(n).ok_or(QueryError::TypeError {
+        message: "Invalid number".to_string(),
+    })?;
+    Ok(Value::Number(num))
+}
+
+// NULL and missing values are skipped by everything except COUNT(*) and ARRAY_AGG
+fn eval_aggregate(
+    func: AggregateFunc,
+    arg: Option<&Expression>,
+    group: &[Value],
+    response: &Value,
+) -> Result<Value, QueryError> {
+    // COUNT(*)
+    let Some(arg) = arg else {
+        return Ok(Value::from(group.len()));
+    };
+
+    let mut values = Vec::with_capacity(group.len());
+    for row in group {
+        values.push(eval_or_null(arg, Scope::row(row, response))?);
+    }
+
+    if func == AggregateFunc::ArrayAgg {
+        return Ok(Value::Array(values));
+    }
+
+    let present: Vec<Value> = values.into_iter().filter(|v| !v.is_null()).collect();
+    match func {
+        AggregateFunc::Count => Ok(Value::from(present.len())),
+        AggregateFunc::Min => Ok(present
+            .into_iter()
+            .min_by(compare_values)
+            .unwrap_or(Value::Null)),
+        AggregateFunc::Max => Ok(present
+            .into_iter()
+            .max_by(compare_values)
+            .unwrap_or(Value::Null)),
+        AggregateFunc::Sum | AggregateFunc::Avg => {
+            if present.is_empty() {
+                return Ok(Value::Null);
+            }
+
+            let mut numbers = Vec::with_capacity(present.len());
+            for v in &present {
+                numbers.push(v.as_number().ok_or(QueryError::TypeError {
+                    message: format!("{:?} expects numbers, got {}", func, v),
+                })?);
+            }
+
+            // Keep integer sums exact when every input is an integer
+            if func == AggregateFunc::Sum
+                && let Some(total) = numbers
+                    .iter()
+                    .try_fold(0i64, |acc, n| n.as_i64().and_then(|n| acc.checked_add(n)))
+            {
+                return Ok(Value::from(total));
+            }
+
+            let total: f64 = numbers.iter().filter_map(|n| n.as_f64()).sum();
+            if func == AggregateFunc::Avg {
+                number_value(total / numbers.len() as f64)
+            } else {
+                number_value(total)
+            }
+        }
+        AggregateFunc::ArrayAgg => unreachable!(),
+    }
+}
+
+fn eval_unary(op: &UnaryOp, value: &Value) -> Result<Value, QueryError> {
+    match op {
+        UnaryOp::Not => {
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+            let b = value.as_bool().ok_or(QueryError::TypeError {
+                message: "Operand of NOT is not a boolean".to_string(),
+            })?;
+            Ok(Value::Bool(!b))
+        }
+        UnaryOp::Neg => {
+            if value.is_null() {
+                return Ok(Value::Null);
+            }
+            if let Some(n) = value.as_i64().and_then(i64::checked_neg) {
+                return Ok(Value::from(n));
+            }
+            let n = value.as_f64().ok_or(QueryError::TypeError {
+                message: "Operand of unary minus is not a number".to_string(),
+            })?;
+            number_value(-n)
+        }
     }
 }
 
 fn eval_binary(left: &Value, op: &BinaryOp, right: &Value) -> Result<Value, QueryError> {
     match op {
-        &BinaryOp::Eq => Ok(Value::Bool(left == right)),
-        &BinaryOp::Neq => Ok(Value::Bool(left != right)),
+        &BinaryOp::Eq => Ok(Value::Bool(values_equal(left, right))),
+        &BinaryOp::Neq => Ok(Value::Bool(!values_equal(left, right))),
         &BinaryOp::Gt | &BinaryOp::Gte | &BinaryOp::Lt | &BinaryOp::Lte => {
-            let ln = left.as_f64().ok_or(QueryError::TypeError {
+            if left.is_null() || right.is_null() {
+                return Ok(Value::Null);
+            }
+            let ln = ordering_operand(left).ok_or(QueryError::TypeError {
                 message: "Left operand is not a number".to_string(),
             })?;
-            let rn = right.as_f64().ok_or(QueryError::TypeError {
+            let rn = ordering_operand(right).ok_or(QueryError::TypeError {
                 message: "Right operand is not a number".to_string(),
             })?;
 
-            let result = match op {
-                &BinaryOp::Gt => ln > rn,
-                &BinaryOp::Gte => ln >= rn,
-                &BinaryOp::Lt => ln < rn,
-                &BinaryOp::Lte => ln <= rn,
+            let result = match *op {
+                BinaryOp::Gt => ln > rn,
+                BinaryOp::Gte => ln >= rn,
+                BinaryOp::Lt => ln < rn,
+                BinaryOp::Lte => ln <= rn,
                 _ => unreachable!(),
             };
             Ok(Value::Bool(result))
         }
 
         &BinaryOp::And | &BinaryOp::Or => {
-            let lb = left.as_bool().ok_or(QueryError::TypeError {
-                message: "Left operand is not a boolean".to_string(),
-            })?;
-            let rb = right.as_bool().ok_or(QueryError::TypeError {
-                message: "Right operand is not a boolean".to_string(),
-            })?;
+            let operand = |v: &Value, side: &str| match v {
+                Value::Null => Ok(None),
+                v => v.as_bool().map(Some).ok_or(QueryError::TypeError {
+                    message: format!("{} operand is not a boolean", side),
+                }),
+            };
+            let lb = operand(left, "Left")?;
+            let rb = operand(right, "Right")?;
 
-            let result = match op {
-                &BinaryOp::And => lb && rb,
-                &BinaryOp::Or => lb || rb,
-                _ => unreachable!(),
+            // Three-valued logic: NULL only decides when the other side can't
+            let result = match (op, lb, rb) {
+                (BinaryOp::And, Some(false), _) | (BinaryOp::And, _, Some(false)) => Some(false),
+                (BinaryOp::Or, Some(true), _) | (BinaryOp::Or, _, Some(true)) => Some(true),
+                // Both sides agree here
+                (_, Some(l), Some(_)) => Some(l),
+                _ => None,
             };
-            Ok(Value::Bool(result))
+            Ok(result.map_or(Value::Null, Value::Bool))
+        }
+
+        &BinaryOp::Add | &BinaryOp::Sub | &BinaryOp::Mul | &BinaryOp::Div | &BinaryOp::Mod => {
+            eval_arithmetic(left, op, right)
+        }
+
+        &BinaryOp::Concat => {
+            // NULL propagates like in SQL
+            match (concat_operand(left)?, concat_operand(right)?) {
+                (Some(l), Some(r)) => Ok(Value::String(l + &r)),
+                _ => Ok(Value::Null),
+            }
         }
 
         &BinaryOp::RegexMatch => {
@@ -190,3 +1082,537 @@ fn eval_binary(left: &Value, op: &BinaryOp, right: &Value) -> Result<Value, Quer
         }
     }
 }
+
+// Numbers compare by value, so 18 == 18.0
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64() == r.as_f64(),
+        _ => left == right,
+    }
+}
+
+// == Predicates ==
+// A NULL operand yields NULL, which WHERE treats as false.
+// Numeric strings, such as header values, compare as the numbers they hold
+fn ordering_operand(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(s) => s.trim().parse::<f64>().ok().filter(|n| n.is_finite()),
+        other => other.as_f64(),
+    }
+}
+
+fn eval_in(value: &Value, list: &Value, negated: bool) -> Result<Value, QueryError> {
+    let Value::Array(items) = list else {
+        return Err(QueryError::TypeError {
+            message: format!("IN expects a list, got {}", list),
+        });
+    };
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    let found = items.iter().any(|item| values_equal(value, item));
+    Ok(Value::Bool(found != negated))
+}
+
+fn eval_between(
+    value: &Value,
+    low: &Value,
+    high: &Value,
+    negated: bool,
+) -> Result<Value, QueryError> {
+    if value.is_null() || low.is_null() || high.is_null() {
+        return Ok(Value::Null);
+    }
+
+    let ordering = |a: &Value, b: &Value| match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    let (Some(above_low), Some(below_high)) = (ordering(value, low), ordering(value, high)) else {
+        return Err(QueryError::TypeError {
+            message: "BETWEEN operands must all be numbers or all be strings".to_string(),
+        });
+    };
+
+    let inside = above_low != Ordering::Less && below_high != Ordering::Greater;
+    Ok(Value::Bool(inside != negated))
+}
+
+fn eval_like(
+    value: &Value,
+    pattern: &Value,
+    case_insensitive: bool,
+    negated: bool,
+) -> Result<Value, QueryError> {
+    if value.is_null() || pattern.is_null() {
+        return Ok(Value::Null);
+    }
+    let text = value.as_str().ok_or(QueryError::TypeError {
+        message: "Left operand of LIKE must be a string".to_string(),
+    })?;
+    let pattern = pattern.as_str().ok_or(QueryError::TypeError {
+        message: "Right operand of LIKE must be a string pattern".to_string(),
+    })?;
+
+    let re = like_regex(pattern, case_insensitive)?;
+    Ok(Value::Bool(re.is_match(text) != negated))
+}
+
+// `%` matches any run of characters, `_` exactly one and `\` escapes the next one
+fn like_regex(pattern: &str, case_insensitive: bool) -> Result<regex::Regex, QueryError> {
+    let mut re = String::from(if case_insensitive { "(?is)^" } else { "(?s)^" });
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    re.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            other => re.push_str(&regex::escape(&other.to_string())),
+        }
+    }
+    re.push('$');
+
+    regex::Regex::new(&re).map_err(|e| QueryError::TypeError {
+        message: format!("Invalid LIKE pattern: {}", e),
+    })
+}
+
+// Integer operands give an integer result when it is exact and fits in i64;
+// everything else is computed as f64. NULL operands yield NULL.
+fn eval_arithmetic(left: &Value, op: &BinaryOp, right: &Value) -> Result<Value, QueryError> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+
+    let (Some(l), Some(r)) = (left.as_number(), right.as_number()) else {
+        return Err(QueryError::TypeError {
+            message: format!(
+                "Operands of {:?} must be numbers, got {} and {}",
+                op, left, right
+            ),
+        });
+    };
+
+    if matches!(op, BinaryOp::Div | BinaryOp::Mod) && r.as_f64() == Some(0.0) {
+        return Err(QueryError::DivisionByZero);
+    }
+
+    if let (Some(a), Some(b)) = (l.as_i64(), r.as_i64()) {
+        let exact = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div => a
+                .checked_rem(b)
+                .filter(|rem| *rem == 0)
+                .and_then(|_| a.checked_div(b)),
+            BinaryOp::Mod => a.checked_rem(b),
+            _ => unreachable!(),
+        };
+        if let Some(v) = exact {
+            return Ok(Value::from(v));
+        }
+    }
+
+    let a = l.as_f64().unwrap_or(f64::NAN);
+    let b = r.as_f64().unwrap_or(f64::NAN);
+    let result = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Mod => a % b,
+        _ => unreachable!(),
+    };
+    number_value(result)
+}
+
+fn concat_operand(value: &Value) -> Result<Option<String>, QueryError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s.clone())),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::Bool(b) => Ok(Some(b.to_string())),
+        other => Err(QueryError::TypeError {
+            message: format!("Cannot concatenate {}", other),
+        }),
+    }
+}
+
+// == Request References ==
+// `{GetOrder.body.user_id}` in a URL or header reads a value from another
+// request's response, so that request has to be fetched first.
+fn reference_regex() -> regex::Regex {
+    regex::Regex::new(r"\{\+?([A-Za-z_][A-Za-z0-9_]*)\.((?:body|response)(?:[.\[][^{}]*)?)\}")
+        .unwrap()
+}
+
+// Request names referenced by `text`, in order of first use
+pub fn request_references(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for caps in reference_regex().captures_iter(text) {
+        if !names.iter().any(|n| n == &caps[1]) {
+            names.push(caps[1].to_string());
+        }
+    }
+    names
+}
+
+// Substitutes every reference to a request found in `sources`. Anything else
+// that looks like a placeholder is left untouched.
+pub fn interpolate_references(text: &str, sources: &Sources) -> Result<String, QueryError> {
+    references_in(text, Escape::None, &reference_regex(), sources)
+}
+
+fn references_in(
+    text: &str,
+    escape: Escape,
+    regex: &regex::Regex,
+    sources: &Sources,
+) -> Result<String, QueryError> {
+    replace_placeholders(text, regex, escape, |caps| match sources.get(&caps[1]) {
+        Some(response) => reference_value(response, &caps[2], &caps[0]).map(Some),
+        None => Ok(None),
+    })
+}
+
+// A copy of `request` with references resolved in its URL, headers and body
+pub fn interpolate_request(
+    request: &RequestBlock,
+    sources: &Sources,
+) -> Result<RequestBlock, QueryError> {
+    let regex = reference_regex();
+    let mut request = request.clone();
+    request.map_texts(|text, escape| references_in(text, escape, &regex, sources))?;
+    Ok(request)
+}
+
+fn reference_value(response: &Value, path: &str, placeholder: &str) -> Result<String, QueryError> {
+    let path = placeholder_path(path, placeholder)?;
+    // `Name.body.x` reads the envelope's body, `Name.response.status` the envelope
+    let path = match path.split_first() {
+        Some((PathSegment::Key(root), rest)) if root == "response" => rest,
+        _ => &path[..],
+    };
+    placeholder_text(resolve_path(response, path)?, placeholder)
+}
+
+// The items a FOR EACH request runs over
+pub fn for_each_items(for_each: &ForEach, sources: &Sources) -> Result<Vec<Value>, QueryError> {
+    source_rows(
+        Some(&for_each.request),
+        &for_each.from_type,
+        &for_each.path,
+        &Value::Null,
+        sources,
+    )
+}
+
+// A copy of a FOR EACH request with `{p}`, `{p.url}` or `{p.tags[0]}` filled in
+// from one item
+pub fn interpolate_item(
+    request: &RequestBlock,
+    alias: &str,
+    item: &Value,
+) -> Result<RequestBlock, QueryError> {
+    let regex = regex::Regex::new(&format!(
+        r"\{{\+?{}(?:[.\[][^{{}}]*)?\}}",
+        regex::escape(alias)
+    ))
+    .unwrap();
+    let mut request = request.clone();
+    request.map_texts(|text, escape| {
+        replace_placeholders(text, &regex, escape, |caps| {
+            let placeholder = &caps[0];
+            let inner = placeholder
+                .trim_start_matches(['{', '+'])
+                .trim_end_matches('}');
+            let path = placeholder_path(inner, placeholder)?;
+            placeholder_text(resolve_path(item, &path[1..])?, placeholder).map(Some)
+        })
+    })?;
+    Ok(request)
+}
+
+fn placeholder_path(path: &str, placeholder: &str) -> Result<Vec<PathSegment>, QueryError> {
+    match crate::parser::parse_expression(path) {
+        Ok(Expression::FieldPathExpr(fp)) => Ok(fp.path),
+        _ => Err(QueryError::TypeError {
+            message: format!("invalid placeholder {}", placeholder),
+        }),
+    }
+}
+
+fn placeholder_text(value: Value, placeholder: &str) -> Result<String, QueryError> {
+    match value {
+        Value::Null => Err(QueryError::TypeError {
+            message: format!("placeholder {} is null", placeholder),
+        }),
+        Value::String(s) => Ok(s),
+        other => Ok(other.to_string()),
+    }
+}
+
+// == Scalar Functions ==
+// Arity is checked by the parser, so implementations may index `args` freely.
+// Unless noted otherwise a NULL argument yields NULL.
+pub struct ScalarFunction {
+    pub name: &'static str,
+    pub min_args: usize,
+    // None for variadic functions
+    pub max_args: Option<usize>,
+    eval: fn(&[Value]) -> Result<Value, QueryError>,
+}
+
+impl ScalarFunction {
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min_args && self.max_args.is_none_or(|max| count <= max)
+    }
+
+    pub fn arity(&self) -> String {
+        match self.max_args {
+            Some(max) if max == self.min_args => max.to_string(),
+            Some(max) => format!("{} to {}", self.min_args, max),
+            None => format!("at least {}", self.min_args),
+        }
+    }
+}
+
+const fn function(
+    name: &'static str,
+    min_args: usize,
+    max_args: Option<usize>,
+    eval: fn(&[Value]) -> Result<Value, QueryError>,
+) -> ScalarFunction {
+    ScalarFunction {
+        name,
+        min_args,
+        max_args,
+        eval,
+    }
+}
+
+static FUNCTIONS: &[ScalarFunction] = &[
+    // string
+    function("lower", 1, Some(1), fn_lower),
+    function("upper", 1, Some(1), fn_upper),
+    function("trim", 1, Some(1), fn_trim),
+    function("length", 1, Some(1), fn_length),
+    function("substr", 2, Some(3), fn_substr),
+    function("replace", 3, Some(3), fn_replace),
+    function("split", 2, Some(2), fn_split),
+    // null handling
+    function("coalesce", 1, None, fn_coalesce),
+    function("nullif", 2, Some(2), fn_nullif),
+    // numeric
+    function("round", 1, Some(2), fn_round),
+    function("abs", 1, Some(1), fn_abs),
+    function("floor", 1, Some(1), fn_floor),
+    function("ceil", 1, Some(1), fn_ceil),
+    // JSON
+    function("json_type", 1, Some(1), fn_json_type),
+    function("keys", 1, Some(1), fn_keys),
+    function("array_length", 1, Some(1), fn_array_length),
+];
+
+pub fn lookup_function(name: &str) -> Option<&'static ScalarFunction> {
+    FUNCTIONS.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+}
+
+fn arg_error(func: &str, expected: &str, got: &Value) -> QueryError {
+    QueryError::TypeError {
+        message: format!("{}() expects {}, got {}", func, expected, got),
+    }
+}
+
+fn string_arg<'a>(func: &str, value: &'a Value) -> Result<Option<&'a str>, QueryError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(s) => Ok(Some(s)),
+        other => Err(arg_error(func, "a string", other)),
+    }
+}
+
+fn number_arg(func: &str, value: &Value) -> Result<Option<f64>, QueryError> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Number(n) => Ok(n.as_f64()),
+        other => Err(arg_error(func, "a number", other)),
+    }
+}
+
+fn map_string(func: &str, value: &Value, f: impl Fn(&str) -> String) -> Result<Value, QueryError> {
+    Ok(string_arg(func, value)?.map_or(Value::Null, |s| Value::String(f(s))))
+}
+
+fn fn_lower(args: &[Value]) -> Result<Value, QueryError> {
+    map_string("lower", &args[0], str::to_lowercase)
+}
+
+fn fn_upper(args: &[Value]) -> Result<Value, QueryError> {
+    map_string("upper", &args[0], str::to_uppercase)
+}
+
+fn fn_trim(args: &[Value]) -> Result<Value, QueryError> {
+    map_string("trim", &args[0], |s| s.trim().to_string())
+}
+
+// Characters in a string or elements in an array
+fn fn_length(args: &[Value]) -> Result<Value, QueryError> {
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::String(s) => Ok(Value::from(s.chars().count())),
+        Value::Array(a) => Ok(Value::from(a.len())),
+        other => Err(arg_error("length", "a string or array", other)),
+    }
+}
+
+// substr(text, start[, count]) with a 1-based start like SQL
+fn fn_substr(args: &[Value]) -> Result<Value, QueryError> {
+    let Some(text) = string_arg("substr", &args[0])? else {
+        return Ok(Value::Null);
+    };
+    let Some(start) = number_arg("substr", &args[1])? else {
+        return Ok(Value::Null);
+    };
+    let count = match args.get(2) {
+        Some(v) => match number_arg("substr", v)? {
+            Some(n) => n.max(0.0) as usize,
+            None => return Ok(Value::Null),
+        },
+        None => usize::MAX,
+    };
+
+    // As in SQL, positions before 1 still use up the length, so
+    // substr('abc', 0, 2) is 'a'
+    let start = start as i64;
+    let (skip, count) = if start < 1 {
+        (0, count.saturating_sub((1 - start) as usize))
+    } else {
+        ((start - 1) as usize, count)
+    };
+    Ok(Value::String(text.chars().skip(skip).take(count).collect()))
+}
+
+fn fn_replace(args: &[Value]) -> Result<Value, QueryError> {
+    let (Some(text), Some(from), Some(to)) = (
+        string_arg("replace", &args[0])?,
+        string_arg("replace", &args[1])?,
+        string_arg("replace", &args[2])?,
+    ) else {
+        return Ok(Value::Null);
+    };
+
+    if from.is_empty() {
+        return Ok(Value::String(text.to_string()));
+    }
+    Ok(Value::String(text.replace(from, to)))
+}
+
+fn fn_split(args: &[Value]) -> Result<Value, QueryError> {
+    let (Some(text), Some(sep)) = (
+        string_arg("split", &args[0])?,
+        string_arg("split", &args[1])?,
+    ) else {
+        return Ok(Value::Null);
+    };
+
+    let parts: Vec<Value> = if sep.is_empty() {
+        text.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        text.split(sep)
+            .map(|p| Value::String(p.to_string()))
+            .collect()
+    };
+    Ok(Value::Array(parts))
+}
+
+// First argument that is not NULL
+fn fn_coalesce(args: &[Value]) -> Result<Value, QueryError> {
+    Ok(args
+        .iter()
+        .find(|v| !v.is_null())
+        .cloned()
+        .unwrap_or(Value::Null))
+}
+
+// NULL when both arguments are equal, otherwise the first one
+fn fn_nullif(args: &[Value]) -> Result<Value, QueryError> {
+    if values_equal(&args[0], &args[1]) {
+        Ok(Value::Null)
+    } else {
+        Ok(args[0].clone())
+    }
+}
+
+fn fn_round(args: &[Value]) -> Result<Value, QueryError> {
+    let Some(n) = number_arg("round", &args[0])? else {
+        return Ok(Value::Null);
+    };
+    let digits = match args.get(1) {
+        Some(v) => match number_arg("round", v)? {
+            Some(d) => d as i32,
+            None => return Ok(Value::Null),
+        },
+        None => 0,
+    };
+
+    if digits <= 0 {
+        let factor = 10f64.powi(-digits);
+        return integral_value((n / factor).round() * factor);
+    }
+    let factor = 10f64.powi(digits);
+    number_value((n * factor).round() / factor)
+}
+
+fn fn_abs(args: &[Value]) -> Result<Value, QueryError> {
+    if let Some(n) = args[0].as_i64().and_then(i64::checked_abs) {
+        return Ok(Value::from(n));
+    }
+    match number_arg("abs", &args[0])? {
+        Some(n) => number_value(n.abs()),
+        None => Ok(Value::Null),
+    }
+}
+
+fn fn_floor(args: &[Value]) -> Result<Value, QueryError> {
+    match number_arg("floor", &args[0])? {
+        Some(n) => integral_value(n.floor()),
+        None => Ok(Value::Null),
+    }
+}
+
+fn fn_ceil(args: &[Value]) -> Result<Value, QueryError> {
+    match number_arg("ceil", &args[0])? {
+        Some(n) => integral_value(n.ceil()),
+        None => Ok(Value::Null),
+    }
+}
+
+// Never NULL: json_type(null) is "null"
+fn fn_json_type(args: &[Value]) -> Result<Value, QueryError> {
+    Ok(Value::String(json_type_name(&args[0]).to_string()))
+}
+
+fn fn_keys(args: &[Value]) -> Result<Value, QueryError> {
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::Object(map) => Ok(Value::Array(
+            map.keys().map(|k| Value::String(k.clone())).collect(),
+        )),
+        other => Err(arg_error("keys", "an object", other)),
+    }
+}
+
+fn fn_array_length(args: &[Value]) -> Result<Value, QueryError> {
+    match &args[0] {
+        Value::Null => Ok(Value::Null),
+        Value::Array(a) => Ok(Value::from(a.len())),
+        other => Err(arg_error("array_length", "an array", other)),
+    }
+}
@@ -0,0 +1,349 @@
+use crate::ast::*;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::DateTime;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use regex::{Captures, Regex};
+
+// == Placeholders ==
+
+// Replaces every match of `regex` with the text `value` returns for it, or
+// leaves the match alone on None. In a JSON body a value landing inside a
+// string literal is escaped for that string; in a URL a value landing in the
+// path or query is percent-encoded unless the placeholder is `{+name}`.
+pub fn replace_placeholders<E>(
+    text: &str,
+    regex: &Regex,
+    escape: Escape,
+    mut value: impl FnMut(&Captures) -> Result<Option<String>, E>,
+) -> Result<String, E> {
+    let mut result = String::new();
+    let mut last = 0;
+    for caps in regex.captures_iter(text) {
+        let placeholder = caps.get(0).unwrap();
+        result.push_str(&text[last..placeholder.start()]);
+        last = placeholder.end();
+        let prefix = &text[..placeholder.start()];
+        let raw = placeholder.as_str().starts_with("{+");
+        match (value(&caps)?, escape) {
+            (Some(v), Escape::Json) if in_json_string(prefix) => result.push_str(&escape_json(&v)),
+            (Some(v), Escape::Url) if !raw && in_url_path(prefix) => {
+                result.push_str(&encode_component(&v))
+            }
+            (Some(v), _) => result.push_str(&v),
+            (None, _) => result.push_str(placeholder.as_str()),
+        }
+    }
+    result.push_str(&text[last..]);
+    Ok(result)
+}
+
+// Whether `prefix` ends inside a JSON string literal
+fn in_json_string(prefix: &str) -> bool {
+    let mut inside = false;
+    let mut escaped = false;
+    for c in prefix.chars() {
+        if escaped {
+            escaped = false;
+        } else if inside && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Whether a placeholder after `prefix` lands past the scheme and host of a
+// URL. One at the very start is the base URL itself, as in `{base}/users`,
+// and one after another placeholder is taken to follow a path.
+fn in_url_path(prefix: &str) -> bool {
+    match prefix.find("://") {
+        Some(i) => prefix[i + 3..].contains(['/', '?', '#', '}']),
+        None => !prefix.is_empty(),
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let quoted = serde_json::to_string(text).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// == Request Bodies ==
+
+impl RequestBody {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RequestBody::Json(_) => "application/json",
+            RequestBody::Form(_) => "application/x-www-form-urlencoded",
+            RequestBody::Raw(_) => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn payload(&self) -> String {
+        match self {
+            RequestBody::Json(text) | RequestBody::Raw(text) => text.clone(),
+            RequestBody::Form(params) => params
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{}={}",
+                        encode_component(&p.key),
+                        encode_component(&p.value)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("&"),
+        }
+    }
+}
+
+// == URL Helpers ==
+
+impl RequestBlock {
+    // The URL with the QUERY section appended, keys and values percent-encoded
+    pub fn full_url(&self) -> String {
+        if self.query_params.is_empty() {
+            return self.url.clone();
+        }
+        let (url, fragment) = match self.url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (self.url.as_str(), None),
+        };
+        let pairs = self
+            .query_params
+            .iter()
+            .map(|p| {
+                format!(
+                    "{}={}",
+                    encode_component(&p.key),
+                    encode_component(&p.value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let separator = match url.find('?') {
+            None => "?",
+            Some(_) if url.ends_with(['?', '&']) => "",
+            Some(_) => "&",
+        };
+
+        let mut result = format!("{}{}{}", url, separator, pairs);
+        if let Some(fragment) = fragment {
+            result.push('#');
+            result.push_str(fragment);
+        }
+        result
+    }
+}
+
+// Sets `name=value` in the query string, replacing an existing value
+pub fn set_query_param(url: &str, name: &str, value: &str) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let pair = format!("{}={}", encode_component(name), encode_component(value));
+
+    let mut pairs: Vec<String> = Vec::new();
+    let mut replaced = false;
+    for existing in query.split('&').filter(|p| !p.is_empty()) {
+        let key = existing.split_once('=').map_or(existing, |(k, _)| k);
+        if key == encode_component(name) {
+            if !replaced {
+                pairs.push(pair.clone());
+                replaced = true;
+            }
+        } else {
+            pairs.push(existing.to_string());
+        }
+    }
+    if !replaced {
+        pairs.push(pair);
+    }
+
+    let mut result = format!("{}?{}", base, pairs.join("&"));
+    if let Some(fragment) = fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+// The RFC 3986 unreserved characters, which stay as they are
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Percent-encodes everything outside the RFC 3986 unreserved set
+pub fn encode_component(text: &str) -> String {
+    utf8_percent_encode(text, UNRESERVED).to_string()
+}
+
+// == Status Handling ==
+
+// What to do with a response, as set by EXPECT STATUS and ON STATUS
+#[derive(Debug, PartialEq)]
+pub enum StatusPolicy<'a> {
+    Accept,
+    // Use this value as the body instead of the response's
+    Return(&'a serde_json::Value),
+    Reject,
+}
+
+const SNIPPET_LEN: usize = 200;
+
+impl RequestBlock {
+    // ON STATUS comes first, so `ON STATUS 204 RETURN []` applies to an expected
+    // status too. Without EXPECT STATUS any 2xx is accepted.
+    pub fn status_policy(&self, status: u16) -> StatusPolicy<'_> {
+        if let Some(branch) = self
+            .on_status
+            .iter()
+            .find(|branch| branch.statuses.iter().any(|r| r.contains(status)))
+        {
+            return StatusPolicy::Return(&branch.value);
+        }
+        let expected = if self.expect_status.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.expect_status.iter().any(|r| r.contains(status))
+        };
+        if expected {
+            StatusPolicy::Accept
+        } else {
+            StatusPolicy::Reject
+        }
+    }
+}
+
+// The start of an error response body on one line, for error messages
+pub fn body_snippet(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+// == Retries ==
+
+impl Retry {
+    pub fn retries_status(&self, status: u16) -> bool {
+        self.statuses.iter().any(|r| r.contains(status))
+    }
+
+    // The wait before retry number `retry`, counting from 1: the server's
+    // Retry-After when it sent one, else BACKOFF doubled for every earlier retry
+    // plus up to half that again. `jitter` is a random number in [0, 1).
+    pub fn delay_ms(&self, retry: u32, retry_after_ms: Option<u64>, jitter: f64) -> u64 {
+        if let Some(ms) = retry_after_ms {
+            return ms;
+        }
+        let base = self
+            .backoff_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(20));
+        base.saturating_add((base as f64 * jitter.clamp(0.0, 1.0) / 2.0) as u64)
+    }
+}
+
+// A Retry-After value in milliseconds, given either as seconds or as an HTTP
+// date such as `Wed, 21 Oct 2015 07:28:00 GMT`. A date in the past means now.
+pub fn parse_retry_after(value: &str, now_unix_ms: u64) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(seconds.saturating_mul(1000));
+    }
+
+    let at_ms = DateTime::parse_from_rfc2822(value).ok()?.timestamp_millis();
+    Some(
+        u64::try_from(at_ms)
+            .unwrap_or(0)
+            .saturating_sub(now_unix_ms),
+    )
+}
+
+// == Auth ==
+
+impl RequestBlock {
+    // The request and URL to send once AUTH is applied. BASIC, BEARER and
+    // APIKEY header set a header, replacing one of the same name; APIKEY query
+    // sets a query parameter. OAUTH2 sends `access_token` as a bearer token.
+    pub fn authorized(&self, url: &str, access_token: Option<&str>) -> (RequestBlock, String) {
+        let mut request = self.clone();
+        let mut url = url.to_string();
+        let Some(auth) = &self.auth else {
+            return (request, url);
+        };
+
+        let header = match &auth.scheme {
+            AuthScheme::Basic { user, password } => {
+                Some(("Authorization", basic_authorization(user, password)))
+            }
+            AuthScheme::Bearer { token } => Some(("Authorization", format!("Bearer {}", token))),
+            AuthScheme::ApiKey {
+                location: ApiKeyLocation::Header,
+                name,
+                value,
+            } => Some((name.as_str(), value.clone())),
+            AuthScheme::ApiKey {
+                location: ApiKeyLocation::Query,
+                name,
+                value,
+            } => {
+                url = set_query_param(&url, name, value);
+                None
+            }
+            AuthScheme::OAuth2 { .. } => {
+                access_token.map(|token| ("Authorization", format!("Bearer {}", token)))
+            }
+        };
+        if let Some((key, value)) = header {
+            request.headers.retain(|h| !h.key.eq_ignore_ascii_case(key));
+            request.headers.push(Header {
+                key: key.to_string(),
+                value,
+                span: auth.span,
+            });
+        }
+        (request, url)
+    }
+}
+
+pub fn basic_authorization(user: &str, password: &str) -> String {
+    format!("Basic {}", BASE64.encode(format!("{}:{}", user, password)))
+}
+
+// The Basic Authorization header of an OAuth2 client credentials request.
+// RFC 6749 §2.3.1 form-urlencodes the client id and secret first, so a `:`
+// in either survives the split on the server.
+pub fn client_credentials_authorization(client_id: &str, secret: &str) -> String {
+    let form_encode = |text: &str| encode_component(text).replace("%20", "+");
+    basic_authorization(&form_encode(client_id), &form_encode(secret))
+}
+
+// The form body of an OAuth2 client credentials token request. The client
+// id and secret go in a Basic Authorization header, which RFC 6749 requires
+// every server to accept.
+pub fn client_credentials_form(scope: Option<&str>) -> String {
+    match scope {
+        Some(scope) => format!(
+            "grant_type=client_credentials&scope={}",
+            encode_component(scope)
+        ),
+        None => "grant_type=client_credentials".to_string(),
+    }
+}
+
+// `access_token` and `expires_in`, in seconds, from a token endpoint's response
+pub fn parse_token_response(body: &serde_json::Value) -> Option<(String, Option<u64>)> {
+    let token = body.get("access_token")?.as_str()?.to_string();
+    let expires_in = body.get("expires_in").and_then(|v| match v {
+        serde_json::Value::String(s) => s.parse().ok(),
+        other => other.as_u64(),
+    });
+    Some((token, expires_in))
+}
@@ -0,0 +1,272 @@
+use crate::ast::*;
+use crate::errors::SignError;
+use crate::request::encode_component;
+use chrono::DateTime;
+use hmac::{Hmac, Mac};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+// == Signing ==
+
+// Adds the headers of the request's SIGN clause. `url` is where the request
+// is about to go, `now` the Unix time in seconds, and `env` looks up the AWS_*
+// variables when the clause names no credentials.
+pub fn sign_request(
+    request: &mut RequestBlock,
+    url: &str,
+    now: u64,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<(), SignError> {
+    let Some(sign) = request.sign.clone() else {
+        return Ok(());
+    };
+    let payload = request
+        .body
+        .as_ref()
+        .map(|b| b.payload())
+        .unwrap_or_default();
+    let parsed = Url::parse(url).map_err(|_| SignError::InvalidUrl(url.to_string()))?;
+    let method = method_name(&request.method);
+
+    let headers = match &sign.scheme {
+        SignScheme::HmacSha256 {
+            secret,
+            header,
+            timestamp_header,
+            canonical,
+        } => {
+            let placeholder = Regex::new(r"\{(method|url|path|query|body|body_sha256|timestamp)\}")
+                .expect("valid regex");
+            let text =
+                placeholder.replace_all(canonical, |caps: &regex::Captures| match &caps[1] {
+                    "method" => method.to_string(),
+                    "url" => url.to_string(),
+                    "path" => parsed.path().to_string(),
+                    "query" => parsed.query().unwrap_or_default().to_string(),
+                    "body" => payload.clone(),
+                    "body_sha256" => sha256_hex(payload.as_bytes()),
+                    _ => now.to_string(),
+                });
+            let mut headers = vec![(
+                header.clone(),
+                hmac_sha256_hex(secret.as_bytes(), text.as_bytes()),
+            )];
+            // The server cannot check a signed timestamp it is not told
+            if canonical.contains("{timestamp}") {
+                headers.push((timestamp_header.clone(), now.to_string()));
+            }
+            headers
+        }
+        SignScheme::AwsSigV4 {
+            service,
+            region,
+            access_key,
+            secret_key,
+            session_token,
+        } => {
+            let (access_key, secret_key, session_token) = match (access_key, secret_key) {
+                (Some(key), Some(secret)) => (key.clone(), secret.clone(), session_token.clone()),
+                _ => (
+                    env("AWS_ACCESS_KEY_ID").ok_or(SignError::MissingCredentials)?,
+                    env("AWS_SECRET_ACCESS_KEY").ok_or(SignError::MissingCredentials)?,
+                    env("AWS_SESSION_TOKEN"),
+                ),
+            };
+            let credentials = AwsCredentials {
+                service,
+                region,
+                access_key: &access_key,
+                secret_key: &secret_key,
+                session_token: session_token.as_deref(),
+            };
+            aws_sigv4_headers(request, method, &parsed, &payload, &credentials, now)?
+        }
+    };
+
+    for (key, value) in headers {
+        request
+            .headers
+            .retain(|h| !h.key.eq_ignore_ascii_case(&key));
+        request.headers.push(Header {
+            key,
+            value,
+            span: sign.span,
+        });
+    }
+    Ok(())
+}
+
+// The lowercase hex HMAC-SHA256 of `data`, as the HMAC-SHA256 scheme sends it
+pub fn hmac_sha256_hex(key: &[u8], data: &[u8]) -> String {
+    hex::encode(hmac_sha256(key, data))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn method_name(method: &HttpMethods) -> &'static str {
+    match method {
+        HttpMethods::Get => "GET",
+        HttpMethods::Post => "POST",
+        HttpMethods::Put => "PUT",
+        HttpMethods::Delete => "DELETE",
+        HttpMethods::Patch => "PATCH",
+    }
+}
+
+// == AWS Signature Version 4 ==
+
+struct AwsCredentials<'a> {
+    service: &'a str,
+    region: &'a str,
+    access_key: &'a str,
+    secret_key: &'a str,
+    session_token: Option<&'a str>,
+}
+
+// The X-Amz-* and Authorization headers for a request. Every header the
+// request already carries is signed along with Host.
+fn aws_sigv4_headers(
+    request: &RequestBlock,
+    method: &str,
+    url: &Url,
+    payload: &str,
+    credentials: &AwsCredentials,
+    now: u64,
+) -> Result<Vec<(String, String)>, SignError> {
+    let invalid = || SignError::InvalidUrl(url.to_string());
+    let time = i64::try_from(now)
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .ok_or_else(|| SignError::Failed(format!("cannot date a signature at {}", now)))?;
+    let date = time.format("%Y%m%d").to_string();
+    let amz_date = time.format("%Y%m%dT%H%M%SZ").to_string();
+    let payload_hash = sha256_hex(payload.as_bytes());
+
+    let mut added = vec![("X-Amz-Date".to_string(), amz_date.clone())];
+    // S3 requires the payload hash as a header
+    if credentials.service == "s3" {
+        added.push(("X-Amz-Content-Sha256".to_string(), payload_hash.clone()));
+    }
+    if let Some(token) = credentials.session_token {
+        added.push(("X-Amz-Security-Token".to_string(), token.to_string()));
+    }
+
+    // Url leaves out a default port, as the Host header does
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().ok_or_else(invalid)?, port),
+        None => url.host_str().ok_or_else(invalid)?.to_string(),
+    };
+    let mut signed: Vec<(String, String)> = vec![("host".to_string(), host)];
+    let existing = request
+        .headers
+        .iter()
+        .filter(|h| !h.key.eq_ignore_ascii_case("authorization"))
+        .map(|h| (h.key.as_str(), h.value.as_str()));
+    for (key, value) in existing.chain(added.iter().map(|(k, v)| (k.as_str(), v.as_str()))) {
+        let key = key.to_ascii_lowercase();
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        match signed.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = format!("{},{}", existing, value),
+            None => signed.push((key, value)),
+        }
+    }
+    signed.sort();
+    let signed_names = signed
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v))
+        .collect();
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        aws_canonical_path(url.path(), credentials.service),
+        aws_canonical_query(url.query().unwrap_or_default()),
+        canonical_headers,
+        signed_names,
+        payload_hash
+    );
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        date, credentials.region, credentials.service
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let mut key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [credentials.region, credentials.service, "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    let signature = hmac_sha256_hex(&key, string_to_sign.as_bytes());
+
+    added.push((
+        "Authorization".to_string(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed_names, signature
+        ),
+    ));
+    Ok(added)
+}
+
+// Each path segment encoded once, and for every service but S3 once more
+fn aws_canonical_path(path: &str, service: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            let encoded = encode_component(&decode(segment));
+            if service == "s3" {
+                encoded
+            } else {
+                encode_component(&encoded)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Query pairs re-encoded and sorted by name, then value. A `+` stays a plus
+// rather than turning into a space.
+fn aws_canonical_query(query: &str) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                encode_component(&decode(key)),
+                encode_component(&decode(value)),
+            )
+        })
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn decode(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().into_owned()
+}
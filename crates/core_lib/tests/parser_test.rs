@@ -128,8 +128,1091 @@ RESPONSE
         println!("  query.from_clause: {:?}", resp.query.from_clause);
         println!("  query.where_clause: {:?}", resp.query.where_clause);
         println!("  query.select_clause: {:?}", resp.query.select_clause);
+        println!("  query.order_by: {:?}", resp.query.order_by);
         println!("  query.limit: {:?}", resp.query.limit);
     }
 
     println!("\n=== Program struct is correctly defined ===\n");
 }
+
+#[test]
+fn test_order_by_clause() {
+    let input = r#"
+RESPONSE
+  FROM body.users
+  ORDER BY age DESC NULLS LAST, name
+"#;
+
+    let program = parse_program(input).unwrap();
+    let order_by = &program.response_blocks[0].query.order_by;
+    assert_eq!(order_by.len(), 2);
+    assert_eq!(order_by[0].direction, SortDirection::Desc);
+    assert_eq!(order_by[0].nulls, Some(NullsOrder::Last));
+    assert_eq!(order_by[1].direction, SortDirection::Asc);
+    assert_eq!(order_by[1].nulls, None);
+}
+
+#[test]
+fn test_invalid_order_by() {
+    let input = r#"
+RESPONSE
+  FROM body
+  ORDER BY age NULLS MIDDLE
+"#;
+
+    assert!(parse_program(input).is_err());
+}
+
+#[test]
+fn test_string_literals_keep_spaces_and_operators() {
+    let input = r#"
+RESPONSE
+  FROM body
+  WHERE title == "a >= b AND c"
+"#;
+
+    let program = parse_program(input).unwrap();
+    let Some(Expression::BinaryOpExpr { right, .. }) =
+        &program.response_blocks[0].query.where_clause
+    else {
+        panic!("expected binary expression");
+    };
+    assert!(matches!(
+        right.as_ref(),
+        Expression::LiteralExpr(Literal::StringLiteral(s), _) if s == "a >= b AND c"
+    ));
+}
+
+#[test]
+fn test_hash_inside_url_and_header() {
+    let input = r##"
+REQUEST GetPage
+  GET https://example.com/docs#section # trailing comment
+  HEADERS
+    X-Tag: abc#123
+    X-Color: "#fff"
+  CACHE 10
+"##;
+
+    let program = parse_program(input).unwrap();
+    let req = &program.request_blocks[0];
+    assert_eq!(req.url, "https://example.com/docs#section");
+    assert_eq!(req.headers.len(), 2);
+    assert_eq!(req.headers[0].key, "X-Tag");
+    assert_eq!(req.headers[0].value, "abc#123");
+    assert_eq!(req.headers[1].value, "#fff");
+}
+
+#[test]
+fn test_inline_header() {
+    let input = r#"
+REQUEST GetUsers
+  GET https://api.test.com/users
+  HEADER Authorization: Bearer abc
+"#;
+
+    let program = parse_program(input).unwrap();
+    let headers = &program.request_blocks[0].headers;
+    assert_eq!(headers.len(), 1);
+    assert_eq!(headers[0].key, "Authorization");
+    assert_eq!(headers[0].value, "Bearer abc");
+}
+
+#[test]
+fn test_error_points_at_token() {
+    let input = "RESPONSE\n  FROM body\n  WHERE age > \n  LIMIT 5\n";
+
+    match parse_program(input) {
+        Err(ParseError::Syntax { span, .. }) => {
+            assert_eq!(span.line, 4);
+            assert_eq!(span.column, 3);
+            assert_eq!(&input[span.start..span.end], "LIMIT");
+        }
+        other => panic!("expected syntax error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn test_unterminated_string() {
+    let input = "RESPONSE\n  WHERE name == \"abc\n";
+
+    let err = parse_program(input).err().unwrap();
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.column), (2, 17));
+}
+
+#[test]
+fn test_apostrophes_in_raw_values() {
+    let input = r#"
+USING
+  owner: O'Brien
+
+REQUEST A
+  GET https://x.com/it's
+  HEADER User-Agent: Bob's client
+  HEADER X-Note: don't
+  QUERY
+    q: rock 'n' roll
+REQUEST B
+  POST https://x.com/notes
+  BODY FORM
+    text: it's 'quoted'
+"#;
+    let program = parse_program(input).unwrap();
+    let a = &program.request_blocks[0];
+    assert_eq!(a.url, "https://x.com/it's");
+    let headers: Vec<&str> = a.headers.iter().map(|h| h.value.as_str()).collect();
+    assert_eq!(headers, ["Bob's client", "don't"]);
+    assert_eq!(a.query_params[0].value, "rock 'n' roll");
+    assert_eq!(
+        program.using_block.unwrap().var_declarations[0].value,
+        "O'Brien"
+    );
+
+    let Some(RequestBody::Form(params)) = &program.request_blocks[1].body else {
+        panic!("expected a form body");
+    };
+    assert_eq!(params[0].value, "it's 'quoted'");
+}
+
+#[test]
+fn test_strings_end_at_newline() {
+    let input = "REQUEST A\n  GET https://x.com\n  HEADER X-Size: 5\" screen\n  HEADER X-B: 1\n";
+
+    let err = parse_program(input).err().unwrap();
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.column), (3, 19));
+    assert_eq!(&input[span.start..span.end], "\" screen");
+}
+
+#[test]
+fn test_nodes_carry_spans() {
+    let input = "REQUEST GetUsers\n  GET https://api.test.com\n\nRESPONSE\n  SELECT { id, name: full_name }\n";
+
+    let program = parse_program(input).unwrap();
+    let req = &program.request_blocks[0];
+    assert_eq!((req.span.line, req.span.column), (1, 1));
+
+    let SelectClause::Objects(fields) = &program.response_blocks[0].query.select_clause else {
+        panic!("expected object select");
+    };
+    assert_eq!(
+        &input[fields[1].span.start..fields[1].span.end],
+        "name: full_name"
+    );
+}
+
+#[test]
+fn test_example_files_parse() {
+    for file in ["example.apisql", "pokemon.apisql"] {
+        let path = format!("{}/../../examples/{}", env!("CARGO_MANIFEST_DIR"), file);
+        let source = std::fs::read_to_string(path).unwrap();
+        assert!(parse_program(&source).is_ok(), "{} failed to parse", file);
+    }
+}
+
+#[test]
+fn test_and_binds_tighter_than_or() {
+    let expr = parse_expression("a == 1 AND b == 2 OR c == 3").unwrap();
+    let Expression::BinaryOpExpr { left, op, .. } = expr else {
+        panic!("expected binary expression");
+    };
+    assert!(matches!(op, BinaryOp::Or));
+    assert!(matches!(
+        *left,
+        Expression::BinaryOpExpr {
+            op: BinaryOp::And,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_parentheses_and_not() {
+    let expr = parse_expression("NOT (a OR b) AND !c").unwrap();
+    let Expression::BinaryOpExpr {
+        left, op, right, ..
+    } = expr
+    else {
+        panic!("expected binary expression");
+    };
+    assert!(matches!(op, BinaryOp::And));
+    assert!(matches!(
+        *left,
+        Expression::UnaryOpExpr {
+            op: UnaryOp::Not,
+            ..
+        }
+    ));
+    assert!(matches!(
+        *right,
+        Expression::UnaryOpExpr {
+            op: UnaryOp::Not,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_unclosed_parenthesis() {
+    assert!(parse_expression("(a == 1 OR b == 2").is_err());
+}
+
+#[test]
+fn test_group_by_and_having() {
+    let input = r#"
+RESPONSE
+  FROM body.orders
+  GROUP BY status, region
+  HAVING COUNT(*) > 1
+  SELECT { status, total: SUM(amount) }
+"#;
+
+    let program = parse_program(input).unwrap();
+    let query = &program.response_blocks[0].query;
+    assert_eq!(query.group_by.len(), 2);
+    assert!(query.having.as_ref().unwrap().contains_aggregate());
+}
+
+#[test]
+fn test_aggregate_not_allowed_in_where() {
+    let input = "RESPONSE\n  WHERE COUNT(*) > 1\n";
+
+    let err = parse_program(input).err().unwrap();
+    let span = err.span().unwrap();
+    assert_eq!(&input[span.start..span.end], "COUNT(*)");
+}
+
+#[test]
+fn test_unknown_function() {
+    let err = parse_expression("FOO(x)").err().unwrap();
+    assert!(err.to_string().contains("unknown function"));
+}
+
+#[test]
+fn test_function_call_arity() {
+    assert!(matches!(
+        parse_expression("Lower(name)").unwrap(),
+        Expression::FunctionCall { ref name, ref args, .. } if name == "lower" && args.len() == 1
+    ));
+
+    let err = parse_expression("substr(name)").err().unwrap();
+    assert!(
+        err.to_string()
+            .contains("substr() takes 2 to 3 argument(s), got 1")
+    );
+    assert!(parse_expression("coalesce()").is_err());
+}
+
+#[test]
+fn test_predicates() {
+    // BETWEEN consumes its own AND, the second one joins the predicates
+    let expr = parse_expression("age NOT BETWEEN 18 AND 30 AND name IS NOT NULL").unwrap();
+    let Expression::BinaryOpExpr {
+        left,
+        op: BinaryOp::And,
+        right,
+        ..
+    } = expr
+    else {
+        panic!("expected AND");
+    };
+    assert!(matches!(
+        *left,
+        Expression::BetweenExpr { negated: true, .. }
+    ));
+    assert!(matches!(
+        *right,
+        Expression::IsNullExpr { negated: true, .. }
+    ));
+
+    assert!(matches!(
+        parse_expression(r#"status IN ("open", "pending")"#).unwrap(),
+        Expression::InExpr { ref list, negated: false, .. }
+            if matches!(**list, Expression::ArrayExpr { ref items, .. } if items.len() == 2)
+    ));
+    assert!(matches!(
+        parse_expression("NOT name ILIKE 'a%'").unwrap(),
+        Expression::UnaryOpExpr { ref operand, .. }
+            if matches!(**operand, Expression::LikeExpr { case_insensitive: true, .. })
+    ));
+    assert!(parse_expression("x IS 1").is_err());
+    assert!(parse_expression("x BETWEEN 1 OR 2").is_err());
+}
+
+#[test]
+fn test_case_expression() {
+    let program = parse_program(
+        r#"
+RESPONSE
+  FROM body.customers
+  SELECT {
+    name: name,
+    tier: CASE
+      WHEN spend > 1000 THEN "gold"
+      WHEN spend > 100 THEN "silver"
+      ELSE "bronze"
+    END
+  }
+"#,
+    )
+    .unwrap();
+    let SelectClause::Objects(fields) = &program.response_blocks[0].query.select_clause else {
+        panic!("expected object select");
+    };
+    assert!(matches!(
+        fields[1].expression,
+        Some(Expression::Case { operand: None, ref branches, else_result: Some(_), .. })
+            if branches.len() == 2
+    ));
+
+    assert!(parse_expression("CASE WHEN a THEN 1").is_err());
+    assert!(parse_expression("CASE ELSE 1 END").is_err());
+}
+
+#[test]
+fn test_limit_and_offset() {
+    let program =
+        parse_program("RESPONSE\n  FROM body\n  SELECT id\n  LIMIT 10 OFFSET 20").unwrap();
+    let query = &program.response_blocks[0].query;
+    assert_eq!((query.limit, query.offset), (Some(10), Some(20)));
+
+    let err = parse_program("RESPONSE\n  FROM body\n  OFFSET -1")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("invalid offset value: -"));
+}
+
+#[test]
+fn test_select_distinct() {
+    let program = parse_program("RESPONSE\n  FROM body\n  SELECT DISTINCT { id }").unwrap();
+    assert!(matches!(
+        program.response_blocks[0].query.distinct,
+        Some(Distinct::Rows)
+    ));
+
+    let program =
+        parse_program("RESPONSE\n  FROM body\n  SELECT DISTINCT ON (user.id, day) { id }").unwrap();
+    assert!(matches!(
+        program.response_blocks[0].query.distinct,
+        Some(Distinct::On(ref keys)) if keys.len() == 2
+    ));
+
+    assert!(parse_program("RESPONSE\n  FROM body\n  SELECT DISTINCT ON id").is_err());
+}
+
+#[test]
+fn test_path_segments() {
+    let Expression::FieldPathExpr(fp) = parse_expression("orders[-1].lines[*].sku").unwrap() else {
+        panic!("expected field path");
+    };
+    assert_eq!(
+        fp.path,
+        vec![
+            PathSegment::Key("orders".to_string()),
+            PathSegment::Index(-1),
+            PathSegment::Key("lines".to_string()),
+            PathSegment::Wildcard,
+            PathSegment::Key("sku".to_string()),
+        ]
+    );
+
+    let Expression::FieldPathExpr(fp) = parse_expression("..id").unwrap() else {
+        panic!("expected field path");
+    };
+    assert_eq!(fp.path, vec![PathSegment::Descendant("id".to_string())]);
+
+    let program = parse_program("RESPONSE\n  FROM body.data[0].*\n  SELECT id").unwrap();
+    let from = &program.response_blocks[0].query.from_clause;
+    assert_eq!(
+        from.path,
+        vec![
+            PathSegment::Key("data".to_string()),
+            PathSegment::Index(0),
+            PathSegment::Wildcard,
+        ]
+    );
+
+    assert!(parse_expression("tags[x]").is_err());
+    assert!(parse_expression("tags[1.5]").is_err());
+
+    // Header names keep their hyphens, so this is not a subtraction
+    let Expression::FieldPathExpr(fp) =
+        parse_expression("response.headers.x-ratelimit-remaining").unwrap()
+    else {
+        panic!("expected field path");
+    };
+    assert_eq!(
+        fp.path.last(),
+        Some(&PathSegment::Key("x-ratelimit-remaining".to_string()))
+    );
+    assert!(matches!(
+        parse_expression("a.b-c").unwrap(),
+        Expression::BinaryOpExpr { .. }
+    ));
+
+    let program = parse_program("RESPONSE\n  FROM response\n  SELECT status").unwrap();
+    let from = &program.response_blocks[0].query.from_clause;
+    assert!(matches!(from.from_type, FromType::Response));
+    assert!(from.path.is_empty());
+}
+
+#[test]
+fn test_from_unnest() {
+    let program = parse_program(
+        "RESPONSE\n  FROM body.orders AS order\n    UNNEST items AS item\n    OUTER UNNEST item.options AS opt\n  SELECT { id: order.id }",
+    )
+    .unwrap();
+    let from = &program.response_blocks[0].query.from_clause;
+    assert_eq!(from.alias.as_deref(), Some("order"));
+    assert_eq!(from.unnest.len(), 2);
+    assert!(!from.unnest[0].outer);
+    assert!(from.unnest[1].outer);
+    assert_eq!(from.unnest[1].alias, "opt");
+
+    assert!(parse_program("RESPONSE\n  FROM body.orders UNNEST items\n  SELECT id").is_err());
+}
+
+#[test]
+fn test_response_binding() {
+    let input = r#"
+REQUEST GetUsers
+  GET https://api.test.com/users
+
+REQUEST GetPosts
+  GET https://api.test.com/posts
+
+RESPONSE FROM GetUsers
+  FROM body.users
+  SELECT id
+
+RESPONSE
+  FROM body.posts
+  SELECT id
+"#;
+    let program = parse_program(input).unwrap();
+    let names: Vec<&str> = program
+        .response_blocks
+        .iter()
+        .map(|r| program.request_for(r).unwrap().name.as_str())
+        .collect();
+    assert_eq!(names, ["GetUsers", "GetPosts"]);
+    assert_eq!(program.response_blocks[0].query.from_clause.path.len(), 1);
+
+    // The short form, and a FROM clause that stays on the RESPONSE line
+    let program = parse_program(
+        "REQUEST A\n  GET https://a.test\nREQUEST B\n  GET https://b.test\nRESPONSE A\n  SELECT id\nRESPONSE FROM items SELECT id",
+    )
+    .unwrap();
+    assert_eq!(
+        program
+            .request_for(&program.response_blocks[0])
+            .unwrap()
+            .name,
+        "A"
+    );
+    assert!(program.response_blocks[1].request.is_none());
+    assert_eq!(
+        program
+            .request_for(&program.response_blocks[1])
+            .unwrap()
+            .name,
+        "B"
+    );
+}
+
+#[test]
+fn test_response_binding_errors() {
+    let err = parse_program("REQUEST A\n  GET https://a.test\nRESPONSE FROM Missing\n  SELECT id")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("unknown request: Missing"));
+    assert_eq!(err.span().map(|s| (s.line, s.column)), Some((3, 15)));
+
+    let err = parse_program(
+        "REQUEST A\n  GET https://a.test\nRESPONSE\n  SELECT id\nRESPONSE A\n  SELECT id",
+    )
+    .err()
+    .unwrap();
+    assert!(
+        err.to_string()
+            .contains("request A already has a RESPONSE block")
+    );
+}
+
+#[test]
+fn test_join() {
+    let input = r#"
+RESPONSE
+  FROM GetOrders.body.data AS o
+    LEFT JOIN GetUsers.body AS u ON o.user_id = u.id
+  SELECT { o.id, u.email }
+
+REQUEST GetOrders
+  GET https://api.test.com/orders
+
+REQUEST GetUsers
+  GET https://api.test.com/users
+"#;
+    let program = parse_program(input).unwrap();
+    let query = &program.response_blocks[0].query;
+    let from = &query.from_clause;
+    assert_eq!(from.request.as_deref(), Some("GetOrders"));
+    assert_eq!(from.path, vec![PathSegment::Key("data".to_string())]);
+    assert_eq!(from.joins.len(), 1);
+    assert_eq!(from.joins[0].kind, JoinKind::Left);
+    assert_eq!(from.joins[0].request.as_deref(), Some("GetUsers"));
+    assert_eq!(from.joins[0].alias, "u");
+    assert_eq!(query.referenced_requests(), ["GetOrders", "GetUsers"]);
+
+    let SelectClause::Objects(fields) = &query.select_clause else {
+        panic!("expected object select");
+    };
+    let aliases: Vec<&str> = fields.iter().map(|f| f.alias.as_str()).collect();
+    assert_eq!(aliases, ["id", "email"]);
+
+    // Not a request name, so this is still a path into the body
+    let program = parse_program("RESPONSE\n  FROM data.body\n  SELECT id").unwrap();
+    assert!(
+        program.response_blocks[0]
+            .query
+            .from_clause
+            .request
+            .is_none()
+    );
+
+    assert!(parse_program("RESPONSE\n  FROM body.a AS a JOIN body.b ON a.id = b.id").is_err());
+}
+
+#[test]
+fn test_request_dependencies() {
+    let input = r#"
+USING
+  base: https://api.test.com
+
+REQUEST GetUser
+  GET {base}/users/{GetLatestOrder.body.user_id}
+  HEADER X-Order: {GetLatestOrder.body.id}
+
+REQUEST GetLatestOrder
+  GET {base}/orders/latest
+
+RESPONSE GetUser
+  SELECT email
+"#;
+    let program = parse_program(input).unwrap();
+    let user = &program.request_blocks[0];
+    assert_eq!(user.depends_on, ["GetLatestOrder"]);
+    // USING variables are filled in, request references are left for the runtime
+    assert_eq!(
+        user.url,
+        "https://api.test.com/users/{GetLatestOrder.body.user_id}"
+    );
+
+    let order: Vec<&str> = program
+        .request_order()
+        .unwrap()
+        .iter()
+        .map(|r| r.name.as_str())
+        .collect();
+    assert_eq!(order, ["GetLatestOrder", "GetUser"]);
+
+    let err = parse_program(
+        "REQUEST A\n  GET https://a.test/{B.body.id}\nREQUEST B\n  GET https://b.test/{A.body.id}",
+    )
+    .err()
+    .unwrap();
+    assert!(
+        err.to_string()
+            .contains("requests depend on each other: A -> B -> A")
+    );
+    assert_eq!(err.span().map(|s| s.line), Some(1));
+}
+
+#[test]
+fn test_for_each_request() {
+    let input = r#"
+REQUEST GetPokemon
+  GET https://pokeapi.co/api/v2/pokemon
+
+REQUEST GetDetails FOR EACH p IN GetPokemon.body.results
+  GET {p.url}
+
+RESPONSE GetDetails
+  SELECT name
+"#;
+    let program = parse_program(input).unwrap();
+    let details = &program.request_blocks[1];
+    let for_each = details.for_each.as_ref().unwrap();
+    assert_eq!(for_each.alias, "p");
+    assert_eq!(for_each.request, "GetPokemon");
+    assert_eq!(for_each.path, vec![PathSegment::Key("results".to_string())]);
+    assert_eq!(details.url, "{p.url}");
+    assert_eq!(details.depends_on, ["GetPokemon"]);
+
+    let err = parse_program("REQUEST A FOR EACH p IN body.items\n  GET {p.url}")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("expected a request such as"));
+}
+
+#[test]
+fn test_paginate() {
+    let request = |clause: &str| {
+        let source = format!("REQUEST A\n  GET https://a.test/items\n  {}", clause);
+        parse_program(&source).map(|p| p.request_blocks[0].paginate.clone().unwrap())
+    };
+
+    let page = request("PAGINATE PAGE page START 0 MAX PAGES 5").unwrap();
+    assert_eq!(
+        page.strategy,
+        PageStrategy::Page {
+            param: "page".to_string(),
+            start: 0
+        }
+    );
+    assert_eq!(page.max_pages, Some(5));
+    assert_eq!(page.max_items, None);
+
+    let offset = request("PAGINATE OFFSET offset MAX ITEMS 500").unwrap();
+    assert_eq!(
+        offset.strategy,
+        PageStrategy::Offset {
+            param: "offset".to_string()
+        }
+    );
+    assert_eq!(offset.max_items, Some(500));
+
+    let cursor =
+        request("PAGINATE CURSOR \"page[after]\" FROM body.meta.next MAX PAGES 3").unwrap();
+    assert_eq!(
+        cursor.strategy,
+        PageStrategy::Cursor {
+            param: "page[after]".to_string(),
+            path: vec![
+                PathSegment::Key("meta".to_string()),
+                PathSegment::Key("next".to_string())
+            ]
+        }
+    );
+
+    let link = request("PAGINATE LINK MAX PAGES 10 MAX ITEMS 1000").unwrap();
+    assert_eq!(link.strategy, PageStrategy::Link);
+
+    let err = request("PAGINATE LINK").err().unwrap();
+    assert!(
+        err.to_string()
+            .contains("expected MAX PAGES or MAX ITEMS after PAGINATE")
+    );
+    assert!(request("PAGINATE SCROLL MAX PAGES 2").is_err());
+}
+
+#[test]
+fn test_request_body() {
+    let body = |section: &str| {
+        let source = format!(
+            "REQUEST A\n  POST https://a.test/search\n  {}\n  HEADER X-A: 1",
+            section
+        );
+        parse_program(&source).map(|p| p.request_blocks[0].body.clone().unwrap())
+    };
+
+    let json =
+        body("BODY JSON\n    {\n      \"q\": \"{term}\",\n      \"tags\": [\"a\", \"b\"]\n    }")
+            .unwrap();
+    assert_eq!(
+        json,
+        RequestBody::Json(
+            "{\n      \"q\": \"{term}\",\n      \"tags\": [\"a\", \"b\"]\n    }".to_string()
+        )
+    );
+    assert_eq!(json.content_type(), "application/json");
+
+    // BODY directly followed by a bracket is JSON too
+    assert!(matches!(body("BODY [1, 2]").unwrap(), RequestBody::Json(text) if text == "[1, 2]"));
+
+    let form = body("BODY FORM\n    q: rust & wasm\n    page: 2").unwrap();
+    assert_eq!(form.payload(), "q=rust%20%26%20wasm&page=2");
+    assert_eq!(form.content_type(), "application/x-www-form-urlencoded");
+
+    let raw = body("BODY RAW \"line one\\nline two\"").unwrap();
+    assert_eq!(raw, RequestBody::Raw("line one\nline two".to_string()));
+
+    assert!(body("BODY XML <a/>").is_err());
+
+    let err = body("BODY JSON { \"a\": 1").unwrap_err();
+    assert!(err.to_string().contains("expected '}' to close this JSON"));
+    assert_eq!(err.span().map(|s| (s.line, s.column)), Some((3, 13)));
+}
+
+#[test]
+fn test_body_variables_are_json_escaped() {
+    let input = "USING\n  term: say \"hi\"\n  size: 10\nREQUEST A\n  POST https://a.test\n  BODY JSON { \"q\": \"{term}\", \"size\": {size} }";
+    let program = parse_program(input).unwrap();
+    assert_eq!(
+        program.request_blocks[0].body,
+        Some(RequestBody::Json(
+            r#"{ "q": "say \"hi\"", "size": 10 }"#.to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_query_section() {
+    let input = r#"
+USING
+  search: rust & wasm
+
+REQUEST Search
+  GET https://api.test/search?sort=new#top
+  QUERY
+    q: "{search}"
+    tag: a b
+    tag: c/d
+  QUERY page: 2
+"#;
+    let program = parse_program(input).unwrap();
+    let request = &program.request_blocks[0];
+    let keys: Vec<&str> = request
+        .query_params
+        .iter()
+        .map(|p| p.key.as_str())
+        .collect();
+    assert_eq!(keys, ["q", "tag", "tag", "page"]);
+    assert_eq!(request.query_params[0].value, "rust & wasm");
+    assert_eq!(
+        request.full_url(),
+        "https://api.test/search?sort=new&q=rust%20%26%20wasm&tag=a%20b&tag=c%2Fd&page=2#top"
+    );
+}
+
+#[test]
+fn test_url_variables_are_encoded() {
+    let input = r#"
+USING
+  base: https://api.test/v1
+  name: Smith & Sons?
+  path: a/b
+
+REQUEST A
+  GET {base}/users/{name}?q={name}&raw={+path}
+"#;
+    let program = parse_program(input).unwrap();
+    assert_eq!(
+        program.request_blocks[0].url,
+        "https://api.test/v1/users/Smith%20%26%20Sons%3F?q=Smith%20%26%20Sons%3F&raw=a/b"
+    );
+
+    // The host part of a URL is left alone
+    let program =
+        parse_program("USING\n  host: localhost:8080\nREQUEST A\n  GET http://{host}/x").unwrap();
+    assert_eq!(program.request_blocks[0].url, "http://localhost:8080/x");
+}
+
+#[test]
+fn test_status_policy() {
+    let input = r#"
+REQUEST A
+  GET https://api.test/items
+  EXPECT STATUS 200..299, 304
+  ON STATUS 404, 410 RETURN []
+  ON STATUS 204 RETURN { "items": [] }
+  ON STATUS 500..599 RETURN NULL
+"#;
+    let program = parse_program(input).unwrap();
+    let request = &program.request_blocks[0];
+    assert_eq!(
+        request.expect_status,
+        [
+            StatusRange {
+                start: 200,
+                end: 299
+            },
+            StatusRange {
+                start: 304,
+                end: 304
+            }
+        ]
+    );
+    assert_eq!(request.on_status.len(), 3);
+
+    assert_eq!(request.status_policy(200), StatusPolicy::Accept);
+    assert_eq!(request.status_policy(304), StatusPolicy::Accept);
+    assert_eq!(request.status_policy(401), StatusPolicy::Reject);
+    assert_eq!(
+        request.status_policy(410),
+        StatusPolicy::Return(&serde_json::json!([]))
+    );
+    // ON STATUS wins over an expected status
+    assert_eq!(
+        request.status_policy(204),
+        StatusPolicy::Return(&serde_json::json!({ "items": [] }))
+    );
+    assert_eq!(
+        request.status_policy(503),
+        StatusPolicy::Return(&serde_json::Value::Null)
+    );
+
+    // Without EXPECT STATUS only 2xx is accepted
+    let program = parse_program("REQUEST A\n  GET https://api.test").unwrap();
+    assert_eq!(
+        program.request_blocks[0].status_policy(201),
+        StatusPolicy::Accept
+    );
+    assert_eq!(
+        program.request_blocks[0].status_policy(302),
+        StatusPolicy::Reject
+    );
+
+    let err = |clause: &str| {
+        let source = format!("REQUEST A\n  GET https://api.test\n  {}", clause);
+        parse_program(&source).unwrap_err().to_string()
+    };
+    assert!(err("EXPECT STATUS 299..200").contains("invalid HTTP status: 299..200"));
+    assert!(err("EXPECT STATUS 42").contains("invalid HTTP status: 42"));
+    assert!(err("EXPECT 200").contains("expected STATUS"));
+    assert!(err("ON STATUS 404 RETURN").contains("line 3, column 23"));
+    assert!(
+        err("ON STATUS 404 RETURN { items: [] }")
+            .contains("RETURN value is not valid JSON: { items: [] }")
+    );
+    assert!(err("ON STATUS 404 RETURN [1, 2").contains("line 3, column 24"));
+}
+
+#[test]
+fn test_retry() {
+    let input = r#"
+REQUEST A
+  GET https://api.test/items
+  RETRY 3 BACKOFF 200 ON 429, 500..599
+REQUEST B
+  GET https://api.test/items
+  RETRY 2
+  ON STATUS 404 RETURN []
+"#;
+    let program = parse_program(input).unwrap();
+    let retry = program.request_blocks[0].retry.as_ref().unwrap();
+    assert_eq!((retry.retries, retry.backoff_ms), (3, 200));
+    assert!(retry.retries_status(429) && retry.retries_status(502));
+    assert!(!retry.retries_status(404));
+
+    // Defaults, and an ON STATUS line after RETRY stays its own clause
+    let b = &program.request_blocks[1];
+    let retry = b.retry.as_ref().unwrap();
+    assert_eq!((retry.retries, retry.backoff_ms), (2, 500));
+    assert!(retry.retries_status(503) && !retry.retries_status(500));
+    assert_eq!(b.on_status.len(), 1);
+
+    // Doubling per retry, plus up to half again as jitter; Retry-After wins
+    assert_eq!(retry.delay_ms(1, None, 0.0), 500);
+    assert_eq!(retry.delay_ms(3, None, 0.0), 2000);
+    assert_eq!(retry.delay_ms(3, None, 0.5), 2500);
+    assert_eq!(retry.delay_ms(3, Some(7000), 0.5), 7000);
+
+    assert_eq!(parse_retry_after(" 3 ", 0), Some(3000));
+    // 2015-10-21T07:28:00Z
+    let at = 1_445_412_480_000;
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", at - 1500),
+        Some(1500)
+    );
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", at + 1),
+        Some(0)
+    );
+    assert_eq!(parse_retry_after("soon", 0), None);
+
+    assert!(parse_program("REQUEST A\n  GET https://api.test\n  RETRY").is_err());
+    assert!(parse_program("REQUEST A\n  GET https://api.test\n  RETRY 2 BACKOFF -1").is_err());
+}
+
+#[test]
+fn test_timeout() {
+    let timeout = |clause: &str| {
+        let source = format!("REQUEST A\n  GET https://api.test\n  {}", clause);
+        parse_program(&source).map(|p| p.request_blocks[0].timeout_ms)
+    };
+    assert_eq!(timeout("TIMEOUT 5s").unwrap(), Some(5000));
+    assert_eq!(timeout("TIMEOUT 1.5s").unwrap(), Some(1500));
+    assert_eq!(timeout("TIMEOUT 250ms").unwrap(), Some(250));
+    assert_eq!(timeout("TIMEOUT 2m").unwrap(), Some(120_000));
+    assert_eq!(timeout("TIMEOUT 10").unwrap(), Some(10_000));
+    assert_eq!(timeout("CACHE 10").unwrap(), None);
+
+    for clause in ["TIMEOUT", "TIMEOUT 5h", "TIMEOUT 0", "TIMEOUT fast"] {
+        let err = timeout(clause).unwrap_err().to_string();
+        assert!(err.contains("invalid TIMEOUT value"), "{}", err);
+    }
+    assert!(
+        timeout("TIMEOUT 5h")
+            .unwrap_err()
+            .to_string()
+            .contains("invalid TIMEOUT value: 5h")
+    );
+}
+
+#[test]
+fn test_auth() {
+    let input = r#"
+USING
+  user: ada
+  key: "k 1"
+
+REQUEST Basic
+  GET https://api.test/a
+  HEADER Authorization: Token old
+  AUTH BASIC {user} "open sesame"
+REQUEST Bearer
+  GET https://api.test/b
+  AUTH BEARER {key}
+REQUEST HeaderKey
+  GET https://api.test/c
+  AUTH APIKEY header X-Api-Key {key}
+REQUEST QueryKey
+  GET https://api.test/d?page=2
+  AUTH APIKEY query api_key {key}
+REQUEST Client
+  GET https://api.test/e
+  AUTH OAUTH2 CLIENT_CREDENTIALS https://auth.test/token app {key} "read write"
+"#;
+    let program = parse_program(input).unwrap();
+    let authorized = |i: usize, token: Option<&str>| {
+        let request = &program.request_blocks[i];
+        let (request, url) = request.authorized(&request.url, token);
+        let headers: Vec<(String, String)> = request
+            .headers
+            .into_iter()
+            .map(|h| (h.key, h.value))
+            .collect();
+        (headers, url)
+    };
+
+    // base64("ada:open sesame"), replacing the hand-written header
+    let (headers, _) = authorized(0, None);
+    assert_eq!(
+        headers,
+        [(
+            "Authorization".to_string(),
+            "Basic YWRhOm9wZW4gc2VzYW1l".to_string()
+        )]
+    );
+    assert_eq!(authorized(1, None).0[0].1, "Bearer k 1");
+    assert_eq!(
+        authorized(2, None).0,
+        [("X-Api-Key".to_string(), "k 1".to_string())]
+    );
+    assert_eq!(
+        authorized(3, None),
+        (
+            vec![],
+            "https://api.test/d?page=2&api_key=k%201".to_string()
+        )
+    );
+    assert_eq!(authorized(4, Some("t0k")).0[0].1, "Bearer t0k");
+
+    let Some(AuthScheme::OAuth2 {
+        token_url, scope, ..
+    }) = program.request_blocks[4].auth.as_ref().map(|a| &a.scheme)
+    else {
+        panic!("expected OAUTH2");
+    };
+    assert_eq!(token_url, "https://auth.test/token");
+    assert_eq!(scope.as_deref(), Some("read write"));
+    assert_eq!(
+        client_credentials_form(scope.as_deref()),
+        "grant_type=client_credentials&scope=read%20write"
+    );
+
+    let err = |clause: &str| {
+        let source = format!("REQUEST A\n  GET https://api.test\n  {}", clause);
+        parse_program(&source).unwrap_err().to_string()
+    };
+    assert!(err("AUTH").contains("expected BASIC, BEARER, APIKEY or OAUTH2 after AUTH"));
+    assert!(err("AUTH DIGEST ada pw").contains("line 3, column 8"));
+    assert!(err("AUTH BASIC ada").contains("expected password after the user"));
+    assert!(err("AUTH APIKEY cookie id 1").contains("expected header or query after APIKEY"));
+    assert!(
+        err("AUTH OAUTH2 https://auth.test/token app secret")
+            .contains("expected CLIENT_CREDENTIALS")
+    );
+}
+
+#[test]
+fn test_oauth2_client_credentials_are_form_encoded() {
+    // base64("my+app:p%3As+w%25rd"), where AUTH BASIC sends the text as is
+    assert_eq!(
+        client_credentials_authorization("my app", "p:s w%rd"),
+        "Basic bXkrYXBwOnAlM0FzK3clMjVyZA=="
+    );
+    assert_eq!(basic_authorization("ada", "a:b"), "Basic YWRhOmE6Yg==");
+}
+
+#[test]
+fn test_sign() {
+    let input = r#"
+REQUEST Hmac
+  GET https://api.test/a
+  SIGN HMAC-SHA256 {secret}
+REQUEST Custom
+  GET https://api.test/b
+  SIGN HMAC-SHA256 "k 1" HEADER X-Sig TIMESTAMP X-Sent-At OVER "{method} {body}"
+REQUEST Aws
+  GET https://api.test/c
+  SIGN AWS_SIGV4 execute-api eu-west-1 KEY id SECRET key TOKEN session
+REQUEST AwsFromEnv
+  GET https://api.test/d
+  SIGN AWS_SIGV4 s3 us-east-1
+"#;
+    let program = parse_program(input).unwrap();
+    let scheme = |i: usize| program.request_blocks[i].sign.clone().unwrap().scheme;
+
+    assert_eq!(
+        scheme(0),
+        SignScheme::HmacSha256 {
+            secret: "{secret}".to_string(),
+            header: "X-Signature".to_string(),
+            timestamp_header: "X-Timestamp".to_string(),
+            canonical: "{method}\n{path}\n{timestamp}\n{body}".to_string()
+        }
+    );
+    assert_eq!(
+        scheme(1),
+        SignScheme::HmacSha256 {
+            secret: "k 1".to_string(),
+            header: "X-Sig".to_string(),
+            timestamp_header: "X-Sent-At".to_string(),
+            canonical: "{method} {body}".to_string()
+        }
+    );
+    assert_eq!(
+        scheme(2),
+        SignScheme::AwsSigV4 {
+            service: "execute-api".to_string(),
+            region: "eu-west-1".to_string(),
+            access_key: Some("id".to_string()),
+            secret_key: Some("key".to_string()),
+            session_token: Some("session".to_string())
+        }
+    );
+    assert_eq!(
+        scheme(3),
+        SignScheme::AwsSigV4 {
+            service: "s3".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: None,
+            secret_key: None,
+            session_token: None
+        }
+    );
+
+    let err = |clause: &str| {
+        let source = format!("REQUEST A\n  GET https://api.test\n  {}", clause);
+        parse_program(&source).unwrap_err().to_string()
+    };
+    assert!(err("SIGN").contains("expected HMAC-SHA256 or AWS_SIGV4 after SIGN"));
+    assert!(err("SIGN RSA key").contains("line 3, column 8"));
+    assert!(err("SIGN HMAC-SHA256").contains("expected secret after HMAC-SHA256"));
+    assert!(err("SIGN HMAC-SHA256 key HEADER").contains("expected header name after HEADER"));
+    assert!(err("SIGN AWS_SIGV4 s3").contains("expected region after the service"));
+    assert!(err("SIGN AWS_SIGV4 s3 us-east-1 KEY id").contains("expected SECRET"));
+}
@@ -0,0 +1,987 @@
+use core_lib::*;
+use serde_json::{Value, json};
+
+fn run(source: &str, body: &Value) -> Value {
+    let program = parse_program(source).unwrap();
+    execute_query(&program.response_blocks[0].query, body).unwrap()
+}
+
+#[test]
+fn test_order_by_before_limit() {
+    let body = json!({ "users": [
+        { "name": "a", "age": 30 },
+        { "name": "b", "age": 50 },
+        { "name": "c", "age": 40 }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.users
+  ORDER BY age DESC
+  SELECT name
+  LIMIT 2
+"#,
+        &body,
+    );
+
+    assert_eq!(result, json!([{ "name": "b" }, { "name": "c" }]));
+}
+
+#[test]
+fn test_order_by_is_stable_with_nulls() {
+    let body = json!({ "items": [
+        { "id": 1, "rank": 2 },
+        { "id": 2 },
+        { "id": 3, "rank": 1 },
+        { "id": 4, "rank": 2 },
+        { "id": 5, "rank": null }
+    ]});
+
+    let ids = |v: Value| -> Vec<i64> {
+        v.as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].as_i64().unwrap())
+            .collect()
+    };
+
+    let asc = run(
+        "RESPONSE\n  FROM body.items\n  ORDER BY rank\n  SELECT id",
+        &body,
+    );
+    assert_eq!(ids(asc), vec![3, 1, 4, 2, 5]);
+
+    let nulls_first = run(
+        "RESPONSE\n  FROM body.items\n  ORDER BY rank ASC NULLS FIRST\n  SELECT id",
+        &body,
+    );
+    assert_eq!(ids(nulls_first), vec![2, 5, 3, 1, 4]);
+}
+
+#[test]
+fn test_order_by_select_alias() {
+    let body = json!({ "users": [
+        { "name": "young", "age": 20 },
+        { "name": "old", "age": 70 }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.users
+  ORDER BY is_senior DESC
+  SELECT {
+    name,
+    is_senior: age >= 60
+  }
+"#,
+        &body,
+    );
+
+    assert_eq!(result[0]["name"], json!("old"));
+}
+
+#[test]
+fn test_nested_boolean_logic() {
+    let body = json!({ "flags": [
+        { "key": "a", "enabled": true, "rollout": 100, "env": "prod" },
+        { "key": "b", "enabled": false, "rollout": 50, "env": "prod" },
+        { "key": "c", "enabled": true, "rollout": 10, "env": "dev" },
+        { "key": "d", "enabled": false, "rollout": 0, "env": "dev" }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.flags
+  WHERE NOT enabled OR rollout > 50 AND env == "prod"
+  SELECT key
+"#,
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([{ "key": "a" }, { "key": "b" }, { "key": "d" }])
+    );
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.flags
+  WHERE (NOT enabled OR rollout > 50) AND env == "prod"
+  SELECT key
+"#,
+        &body,
+    );
+    assert_eq!(result, json!([{ "key": "a" }, { "key": "b" }]));
+}
+
+#[test]
+fn test_unary_minus() {
+    let body = json!({ "points": [{ "x": 3 }, { "x": -5 }] });
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.points
+  WHERE -x > 0
+  SELECT { neg: -x, x }
+"#,
+        &body,
+    );
+    assert_eq!(result, json!([{ "neg": 5, "x": -5 }]));
+}
+
+fn orders() -> Value {
+    json!({ "orders": [
+        { "status": "open", "amount": 10, "customer": "a" },
+        { "status": "closed", "amount": 5.5, "customer": "b" },
+        { "status": "open", "amount": 30, "customer": "c" },
+        { "status": "open", "amount": null, "customer": "d" },
+        { "status": "closed", "amount": 2, "customer": "e" }
+    ]})
+}
+
+#[test]
+fn test_group_by_aggregates() {
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders
+  GROUP BY status
+  SELECT {
+    status,
+    orders: COUNT(*),
+    priced: COUNT(amount),
+    total: SUM(amount),
+    average: AVG(amount),
+    smallest: MIN(amount),
+    largest: MAX(amount),
+    customers: ARRAY_AGG(customer)
+  }
+"#,
+        &orders(),
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            {
+                "status": "open", "orders": 3, "priced": 2, "total": 40,
+                "average": 20.0, "smallest": 10, "largest": 30,
+                "customers": ["a", "c", "d"]
+            },
+            {
+                "status": "closed", "orders": 2, "priced": 2, "total": 7.5,
+                "average": 3.75, "smallest": 2, "largest": 5.5,
+                "customers": ["b", "e"]
+            }
+        ])
+    );
+}
+
+#[test]
+fn test_having_and_order_by_aggregate() {
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders
+  GROUP BY status
+  HAVING SUM(amount) > 10
+  ORDER BY total DESC
+  SELECT { status, total: SUM(amount) }
+"#,
+        &orders(),
+    );
+
+    assert_eq!(result, json!([{ "status": "open", "total": 40 }]));
+}
+
+#[test]
+fn test_aggregate_without_group_by() {
+    let result = run(
+        "RESPONSE\n  FROM body.orders\n  SELECT { total: COUNT(*) }",
+        &orders(),
+    );
+    assert_eq!(result, json!([{ "total": 5 }]));
+
+    let empty = run(
+        "RESPONSE\n  FROM body.orders\n  WHERE amount > 1000\n  SELECT { total: COUNT(*), sum: SUM(amount) }",
+        &orders(),
+    );
+    assert_eq!(empty, json!([{ "total": 0, "sum": null }]));
+}
+
+#[test]
+fn test_arithmetic_and_concat() {
+    let body = json!({ "items": [
+        { "name": "pen", "price": 1.5, "qty": 4, "sold": 3 },
+        { "name": "book", "price": 12, "qty": 2, "sold": 2 }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.items
+  WHERE price * qty >= 6
+  SELECT {
+    total: price * qty,
+    left: qty - sold,
+    pct: sold * 100 / qty,
+    odd: qty % 3,
+    label: name || " x" || qty,
+    rounded: 1 + 2 * 3
+  }
+"#,
+        &body,
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            { "total": 6.0, "left": 1, "pct": 75, "odd": 1, "label": "pen x4", "rounded": 7 },
+            { "total": 24, "left": 0, "pct": 100, "odd": 2, "label": "book x2", "rounded": 7 }
+        ])
+    );
+}
+
+#[test]
+fn test_integer_literal_equality() {
+    let body = json!({ "users": [{ "age": 18 }, { "age": 18.0 }, { "age": 19 }] });
+
+    let result = run(
+        "RESPONSE\n  FROM body.users\n  WHERE age == 18\n  SELECT age",
+        &body,
+    );
+    assert_eq!(result.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_division_by_zero() {
+    let program = parse_program("RESPONSE\n  FROM body\n  SELECT { r: a / b }").unwrap();
+    let err = execute_query(
+        &program.response_blocks[0].query,
+        &json!({ "a": 1, "b": 0 }),
+    )
+    .unwrap_err();
+    assert!(matches!(err, QueryError::DivisionByZero));
+
+    // WHERE fails the query too rather than dropping the row
+    let program =
+        parse_program("RESPONSE\n  FROM body.rows\n  WHERE a / b > 1\n  SELECT a").unwrap();
+    let err = execute_query(
+        &program.response_blocks[0].query,
+        &json!({ "rows": [{ "a": 1, "b": 0 }] }),
+    )
+    .unwrap_err();
+    assert!(matches!(err, QueryError::DivisionByZero));
+
+    // A row missing the field is still only filtered out
+    let result = run(
+        "RESPONSE\n  FROM body.rows\n  WHERE a / b > 1 OR c\n  SELECT a",
+        &json!({ "rows": [{ "a": 4, "b": 2 }, { "a": 1 }] }),
+    );
+    assert_eq!(result, json!([{ "a": 4 }]));
+}
+
+#[test]
+fn test_scalar_functions() {
+    let body = json!({ "users": [
+        { "name": "  Ada Lovelace ", "score": 91.456, "tags": ["a", "b"] },
+        { "name": "bob", "score": -3.5, "nick": "bobby", "tags": [] }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.users
+  WHERE length(trim(name)) > 2
+  ORDER BY lower(name)
+  SELECT {
+    name: upper(trim(name)),
+    first: substr(trim(name), 1, 3),
+    nick: coalesce(nick, "none"),
+    score: round(score, 1),
+    whole: abs(floor(score)),
+    tags: array_length(tags),
+    kind: json_type(tags),
+    parts: split(replace(trim(name), " ", "-"), "-")
+  }
+"#,
+        &body,
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            { "name": "ADA LOVELACE", "first": "Ada", "nick": "none", "score": 91.5,
+              "whole": 91, "tags": 2, "kind": "array", "parts": ["Ada", "Lovelace"] },
+            { "name": "BOB", "first": "bob", "nick": "bobby", "score": -3.5,
+              "whole": 4, "tags": 0, "kind": "array", "parts": ["bob"] }
+        ])
+    );
+}
+
+#[test]
+fn test_functions_over_aggregates() {
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders
+  GROUP BY status
+  SELECT { status: status, avg: round(AVG(amount), 2), n: nullif(COUNT(*), 3) }
+"#,
+        &orders(),
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            { "status": "open", "avg": 20.0, "n": null },
+            { "status": "closed", "avg": 3.75, "n": 2 }
+        ])
+    );
+}
+
+#[test]
+fn test_function_type_error() {
+    let program = parse_program("RESPONSE\n  FROM body\n  SELECT { n: upper(x) }").unwrap();
+    let err = execute_query(&program.response_blocks[0].query, &json!({ "x": 1 })).unwrap_err();
+    assert!(err.to_string().contains("upper() expects a string"));
+}
+
+#[test]
+fn test_predicates() {
+    let body = json!({ "tickets": [
+        { "id": 1, "status": "open", "title": "Fix 100% CPU", "age": 3 },
+        { "id": 2, "status": "pending", "title": "crash on start", "age": 10, "owner": null },
+        { "id": 3, "status": "closed", "title": "Crash in parser", "age": 40, "owner": "kim" },
+        { "id": 4, "status": "open", "title": "typo", "age": 25, "owner": "lee" }
+    ]});
+    let ids = |where_clause: &str| {
+        let source = format!(
+            "RESPONSE\n  FROM body.tickets\n  WHERE {}\n  SELECT id",
+            where_clause
+        );
+        let result = run(&source, &body);
+        result
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["id"].as_i64().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(ids(r#"status IN ("open", "pending")"#), [1, 2, 4]);
+    assert_eq!(ids(r#"status NOT IN ["open", "pending"]"#), [3]);
+    assert_eq!(ids("age BETWEEN 10 AND 25"), [2, 4]);
+    assert_eq!(ids("age NOT BETWEEN 10 AND 25 AND id > 1"), [3]);
+    assert_eq!(ids(r#"title LIKE "%crash%""#), [2]);
+    assert_eq!(ids(r#"title ILIKE "%crash%""#), [2, 3]);
+    assert_eq!(ids(r#"title LIKE "Fix 100\\% ___""#), [1]);
+    assert_eq!(ids(r#"title NOT LIKE "_r%""#), [1, 4]);
+    assert_eq!(ids("owner IS NULL"), [1, 2]);
+    assert_eq!(ids("owner IS NOT NULL"), [3, 4]);
+}
+
+#[test]
+fn test_array_literals() {
+    let body = json!({ "items": [{ "k": "a" }, { "k": "b" }, { "k": "c" }] });
+    let result = run(
+        r#"RESPONSE
+  FROM body.items
+  SELECT { k: k, ok: k IN ["a", "c"], pair: [1, 2] }"#,
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([
+            { "k": "a", "ok": true, "pair": [1, 2] },
+            { "k": "b", "ok": false, "pair": [1, 2] },
+            { "k": "c", "ok": true, "pair": [1, 2] }
+        ])
+    );
+}
+
+#[test]
+fn test_case_when() {
+    let body = json!({ "customers": [
+        { "name": "a", "spend": 1500, "country": "NL" },
+        { "name": "b", "spend": 150, "country": "DE" },
+        { "name": "c", "spend": 15, "country": null }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.customers
+  WHERE CASE WHEN spend > 100 THEN true ELSE name == "c" END
+  SELECT {
+    name: name,
+    tier: CASE WHEN spend > 1000 THEN "gold" WHEN spend > 100 THEN "silver" ELSE "bronze" END,
+    region: CASE country WHEN "NL" THEN "benelux" WHEN "DE" THEN "dach" END
+  }
+"#,
+        &body,
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            { "name": "a", "tier": "gold", "region": "benelux" },
+            { "name": "b", "tier": "silver", "region": "dach" },
+            { "name": "c", "tier": "bronze", "region": null }
+        ])
+    );
+}
+
+#[test]
+fn test_offset_pages_after_order_by() {
+    let body =
+        json!({ "items": [{ "id": 3 }, { "id": 1 }, { "id": 5 }, { "id": 2 }, { "id": 4 }] });
+    let page = |clause: &str| {
+        run(
+            &format!(
+                "RESPONSE\n  FROM body.items\n  ORDER BY id\n  SELECT id\n  {}",
+                clause
+            ),
+            &body,
+        )
+    };
+
+    assert_eq!(page("LIMIT 2 OFFSET 0"), json!([{ "id": 1 }, { "id": 2 }]));
+    assert_eq!(page("LIMIT 2 OFFSET 2"), json!([{ "id": 3 }, { "id": 4 }]));
+    assert_eq!(page("OFFSET 4\n  LIMIT 2"), json!([{ "id": 5 }]));
+    assert_eq!(page("OFFSET 9"), json!([]));
+}
+
+#[test]
+fn test_select_distinct() {
+    // Overlapping pages repeat records, sometimes with reordered keys or 2.0 for 2
+    let body = json!({ "items": [
+        { "id": 1, "meta": { "a": 1, "b": 2 } },
+        { "id": 2, "meta": { "a": 1 } },
+        { "meta": { "b": 2.0, "a": 1 }, "id": 1 },
+        { "id": 3, "meta": { "a": 1 } }
+    ]});
+
+    let result = run(
+        "RESPONSE\n  FROM body.items\n  SELECT DISTINCT { id, meta }\n  LIMIT 3",
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([
+            { "id": 1, "meta": { "a": 1, "b": 2 } },
+            { "id": 2, "meta": { "a": 1 } },
+            { "id": 3, "meta": { "a": 1 } }
+        ])
+    );
+
+    let result = run(
+        "RESPONSE\n  FROM body.items\n  SELECT DISTINCT meta.a",
+        &body,
+    );
+    assert_eq!(result, json!([{ "a": 1 }]));
+}
+
+#[test]
+fn test_distinct_on_keeps_first_after_ordering() {
+    let body = json!({ "events": [
+        { "user": "a", "at": 3, "kind": "login" },
+        { "user": "b", "at": 1, "kind": "login" },
+        { "user": "a", "at": 7, "kind": "logout" },
+        { "user": "b", "at": 4, "kind": "click" },
+        { "user": null, "at": 5, "kind": "ping" }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.events
+  ORDER BY who, at DESC
+  SELECT DISTINCT ON (who) { who: user, kind }
+"#,
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([
+            { "who": "a", "kind": "logout" },
+            { "who": "b", "kind": "click" },
+            { "who": null, "kind": "ping" }
+        ])
+    );
+}
+
+#[test]
+fn test_path_indexing_and_wildcards() {
+    let body = json!({ "orders": [
+        { "id": 1, "tags": ["new", "gift"], "lines": [{ "sku": "a", "price": 2 }, { "sku": "b", "price": 3 }] },
+        { "id": 2, "tags": [], "lines": [{ "sku": "c", "price": 10 }] }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders
+  WHERE "gift" IN tags[*] OR tags[0] IS NULL
+  SELECT {
+    id,
+    first: tags[0],
+    last: tags[-1],
+    skus: lines[*].sku,
+    last_price: lines[-1].price
+  }
+"#,
+        &body,
+    );
+
+    assert_eq!(
+        result,
+        json!([
+            { "id": 1, "first": "new", "last": "gift", "skus": ["a", "b"], "last_price": 3 },
+            { "id": 2, "first": null, "last": null, "skus": ["c"], "last_price": 10 }
+        ])
+    );
+}
+
+#[test]
+fn test_from_nested_arrays_and_recursive_descent() {
+    let body = json!({ "pages": [
+        { "data": [{ "id": 1, "owner": { "id": 10 } }, { "id": 2 }] },
+        { "data": [{ "id": 3 }] }
+    ]});
+
+    let result = run("RESPONSE\n  FROM body.pages[*].data[*]\n  SELECT id", &body);
+    assert_eq!(result, json!([{ "id": 1 }, { "id": 2 }, { "id": 3 }]));
+
+    let result = run("RESPONSE\n  FROM body..missing\n  SELECT id", &body);
+    assert_eq!(result, json!([]));
+
+    let result = run(
+        "RESPONSE\n  FROM body.pages[0]\n  SELECT { ids: ..id, owners: data[*].owner.id }",
+        &body,
+    );
+    assert_eq!(result, json!([{ "ids": [1, 10, 2], "owners": [10] }]));
+}
+
+#[test]
+fn test_unnest() {
+    let body = json!({ "orders": [
+        { "id": 1, "items": [{ "sku": "a", "qty": 2 }, { "sku": "b", "qty": 1 }] },
+        { "id": 2, "items": [] },
+        { "id": 3 },
+        { "id": 4, "items": [{ "sku": "c", "qty": 5 }] }
+    ]});
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders AS order UNNEST items AS item
+  WHERE item.qty > 1
+  SELECT { order: order.id, sku: item.sku, again: id }
+"#,
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([
+            { "order": 1, "sku": "a", "again": 1 },
+            { "order": 4, "sku": "c", "again": 4 }
+        ])
+    );
+
+    let result = run(
+        r#"
+RESPONSE
+  FROM body.orders OUTER UNNEST items AS item
+  SELECT { id, sku: item.sku }
+"#,
+        &body,
+    );
+    assert_eq!(
+        result,
+        json!([
+            { "id": 1, "sku": "a" },
+            { "id": 1, "sku": "b" },
+            { "id": 2, "sku": null },
+            { "id": 3, "sku": null },
+            { "id": 4, "sku": "c" }
+        ])
+    );
+}
+
+fn join_sources() -> Sources {
+    let mut sources = Sources::new();
+    sources.insert(
+        "GetOrders".to_string(),
+        json!({ "body": { "data": [
+            { "id": 1, "user_id": 10, "total": 5 },
+            { "id": 2, "user_id": 20, "total": 50 },
+            { "id": 3, "user_id": 99, "total": 7 },
+            { "id": 4, "user_id": null, "total": 1 },
+            { "id": 5, "user_id": 10, "total": 70 }
+        ]}}),
+    );
+    sources.insert(
+        "GetUsers".to_string(),
+        json!({ "body": [
+            { "id": 10, "email": "a@test", "vip": true },
+            { "id": 20, "email": "b@test", "vip": false }
+        ]}),
+    );
+    sources
+}
+
+fn run_join(source: &str) -> Value {
+    let program = parse_program(source).unwrap();
+    execute_query_with_sources(
+        &program.response_blocks[0].query,
+        &json!({}),
+        &join_sources(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_inner_and_left_join() {
+    let inner = run_join(
+        r#"
+REQUEST GetOrders
+  GET https://api.test.com/orders
+REQUEST GetUsers
+  GET https://api.test.com/users
+
+RESPONSE GetOrders
+  FROM GetOrders.body.data AS o JOIN GetUsers.body AS u ON o.user_id = u.id
+  SELECT { o.id, u.email }
+"#,
+    );
+    assert_eq!(
+        inner,
+        json!([
+            { "id": 1, "email": "a@test" },
+            { "id": 2, "email": "b@test" },
+            { "id": 5, "email": "a@test" }
+        ])
+    );
+
+    let left = run_join(
+        r#"
+REQUEST GetOrders
+  GET https://api.test.com/orders
+REQUEST GetUsers
+  GET https://api.test.com/users
+
+RESPONSE GetOrders
+  FROM GetOrders.body.data AS o
+    LEFT JOIN GetUsers.body AS u ON u.id = o.user_id AND u.vip
+  WHERE total < 60
+  SELECT { id, email: u.email }
+"#,
+    );
+    assert_eq!(
+        left,
+        json!([
+            { "id": 1, "email": "a@test" },
+            { "id": 2, "email": null },
+            { "id": 3, "email": null },
+            { "id": 4, "email": null }
+        ])
+    );
+}
+
+#[test]
+fn test_join_missing_source() {
+    let program = parse_program(
+        "REQUEST Other\n  GET https://x.test\nRESPONSE\n  FROM Other.body\n  SELECT id",
+    )
+    .unwrap();
+    let err = execute_query(&program.response_blocks[0].query, &json!({})).unwrap_err();
+    assert!(matches!(err, QueryError::MissingSource { ref request } if request == "Other"));
+}
+
+#[test]
+fn test_interpolate_references() {
+    let mut sources = Sources::new();
+    sources.insert(
+        "GetOrder".to_string(),
+        json!({ "body": { "user_id": 7, "tags": ["a", "b"], "note": null } }),
+    );
+
+    let url = interpolate_references(
+        "https://api.test/users/{GetOrder.body.user_id}?tag={GetOrder.body.tags[-1]}&x={Other.body.id}",
+        &sources,
+    )
+    .unwrap();
+    assert_eq!(url, "https://api.test/users/7?tag=b&x={Other.body.id}");
+
+    assert!(interpolate_references("{GetOrder.body.note}", &sources).is_err());
+
+    // Request URLs encode the value unless the placeholder is `{+...}`
+    sources.insert(
+        "GetPage".to_string(),
+        json!({ "body": { "next": "/items?page=2", "q": "a b" } }),
+    );
+    let program = parse_program(
+        "REQUEST GetPage\n  GET https://api.test/items\nREQUEST Next\n  GET https://api.test{+GetPage.body.next}&q={GetPage.body.q}",
+    )
+    .unwrap();
+    let next = interpolate_request(&program.request_blocks[1], &sources).unwrap();
+    assert_eq!(next.url, "https://api.test/items?page=2&q=a%20b");
+    assert!(interpolate_references("{GetOrder.body.missing}", &sources).is_err());
+}
+
+#[test]
+fn test_for_each_items() {
+    let program = parse_program(
+        "REQUEST List\n  GET https://api.test/list\nREQUEST Detail FOR EACH p IN List.body.results\n  GET {p.url}?id={p.ids[0]}&v={version}\n  HEADER X-Item: {p}",
+    )
+    .unwrap();
+    let detail = &program.request_blocks[1];
+    let for_each = detail.for_each.as_ref().unwrap();
+
+    let mut sources = Sources::new();
+    sources.insert(
+        "List".to_string(),
+        json!({ "body": { "results": [{ "url": "https://api.test/1", "ids": [7] }] } }),
+    );
+    let items = for_each_items(for_each, &sources).unwrap();
+    assert_eq!(items.len(), 1);
+
+    let request = interpolate_item(detail, &for_each.alias, &items[0]).unwrap();
+    assert_eq!(request.url, "https://api.test/1?id=7&v={version}");
+    assert_eq!(
+        request.headers[0].value,
+        r#"{"ids":[7],"url":"https://api.test/1"}"#
+    );
+
+    assert!(interpolate_item(detail, &for_each.alias, &json!({ "url": null })).is_err());
+}
+
+#[test]
+fn test_response_envelope() {
+    let headers = vec![
+        ("X-RateLimit-Remaining".to_string(), "3".to_string()),
+        ("Set-Cookie".to_string(), "a=1".to_string()),
+        ("set-cookie".to_string(), "b=2".to_string()),
+    ];
+    let response = response_envelope(
+        "https://api.test/users",
+        200,
+        &headers,
+        json!({ "users": [{ "id": 1, "age": 30 }, { "id": 2, "age": 50 }] }),
+        12,
+    );
+
+    let program = parse_program(
+        r#"
+RESPONSE
+  FROM response
+  SELECT { status, url, elapsed_ms, remaining: headers.x-ratelimit-remaining, headers.set-cookie }
+"#,
+    )
+    .unwrap();
+    let meta = execute_query_with_sources(
+        &program.response_blocks[0].query,
+        &response,
+        &Sources::new(),
+    )
+    .unwrap();
+    assert_eq!(
+        meta,
+        json!([{
+            "status": 200,
+            "url": "https://api.test/users",
+            "elapsed_ms": 12,
+            "remaining": "3",
+            "set-cookie": "a=1, b=2"
+        }])
+    );
+
+    // Body rows can still be filtered on the envelope
+    let program = parse_program(
+        r#"
+RESPONSE
+  FROM body.users
+  WHERE age > 40 AND response.status = 200 AND response.headers.x-ratelimit-remaining = "3"
+  SELECT id
+"#,
+    )
+    .unwrap();
+    let rows = execute_query_with_sources(
+        &program.response_blocks[0].query,
+        &response,
+        &Sources::new(),
+    )
+    .unwrap();
+    assert_eq!(rows, json!([{ "id": 2 }]));
+
+    // Header values are strings, but compare as numbers
+    for cond in ["> 2", ">= \"3\"", "< 10"] {
+        let source = format!(
+            "RESPONSE\n  FROM body.users\n  WHERE response.headers.x-ratelimit-remaining {}\n  SELECT id",
+            cond
+        );
+        let program = parse_program(&source).unwrap();
+        let rows = execute_query_with_sources(
+            &program.response_blocks[0].query,
+            &response,
+            &Sources::new(),
+        )
+        .unwrap();
+        assert_eq!(rows, json!([{ "id": 1 }, { "id": 2 }]), "{}", cond);
+    }
+}
+
+fn paginated(clause: &str, response: &str) -> (Program, RequestBlock, Paginate) {
+    let source = format!(
+        "REQUEST A\n  GET https://a.test/items?size=2\n  {}\nRESPONSE\n{}",
+        clause, response
+    );
+    let program = parse_program(&source).unwrap();
+    let request = program.request_blocks[0].clone();
+    let paginate = request.paginate.clone().unwrap();
+    (program, request, paginate)
+}
+
+#[test]
+fn test_paginate_page_strategy() {
+    let (program, request, paginate) = paginated(
+        "PAGINATE PAGE page MAX PAGES 10",
+        "  FROM body.data\n  SELECT id",
+    );
+    let plan = page_plan(&program, &request);
+    assert_eq!(plan.items_path, vec![PathSegment::Key("data".to_string())]);
+    assert_eq!(plan.target, None);
+
+    let mut pages = Paginator::new(&request, &paginate, plan);
+    let mut urls = Vec::new();
+    let bodies = [
+        json!({ "data": [{ "id": 1 }, { "id": 2 }], "total": 3 }),
+        json!({ "data": [{ "id": 3 }], "total": 3 }),
+        json!({ "data": [], "total": 3 }),
+    ];
+    for body in bodies {
+        urls.push(pages.next_url().unwrap().to_string());
+        pages.add_page(body, &[]).unwrap();
+    }
+    assert!(pages.next_url().is_none());
+    assert_eq!(
+        urls,
+        [
+            "https://a.test/items?size=2&page=1",
+            "https://a.test/items?size=2&page=2",
+            "https://a.test/items?size=2&page=3",
+        ]
+    );
+    assert_eq!(
+        pages.finish().unwrap(),
+        json!({ "data": [{ "id": 1 }, { "id": 2 }, { "id": 3 }], "total": 3 })
+    );
+}
+
+#[test]
+fn test_paginate_guards_and_early_stop() {
+    // LIMIT without ORDER BY only needs the first three items
+    let (program, request, paginate) = paginated(
+        "PAGINATE OFFSET offset MAX PAGES 10",
+        "  FROM body\n  SELECT id\n  LIMIT 3",
+    );
+    let plan = page_plan(&program, &request);
+    assert_eq!(plan.target, Some(3));
+
+    let mut pages = Paginator::new(&request, &paginate, plan);
+    assert_eq!(
+        pages.next_url(),
+        Some("https://a.test/items?size=2&offset=0")
+    );
+    pages.add_page(json!([1, 2]), &[]).unwrap();
+    assert_eq!(
+        pages.next_url(),
+        Some("https://a.test/items?size=2&offset=2")
+    );
+    pages.add_page(json!([3, 4]), &[]).unwrap();
+    assert!(pages.next_url().is_none());
+    assert_eq!(pages.finish().unwrap(), json!([1, 2, 3, 4]));
+
+    // ORDER BY needs every row
+    let (program, request, _) = paginated(
+        "PAGINATE OFFSET offset MAX PAGES 10",
+        "  FROM body\n  SELECT id\n  ORDER BY id\n  LIMIT 3",
+    );
+    assert_eq!(page_plan(&program, &request).target, None);
+
+    let (program, request, paginate) =
+        paginated("PAGINATE PAGE page MAX ITEMS 3", "  FROM body\n  SELECT id");
+    let mut pages = Paginator::new(&request, &paginate, page_plan(&program, &request));
+    pages.add_page(json!([1, 2]), &[]).unwrap();
+    pages.add_page(json!([3, 4]), &[]).unwrap();
+    assert!(pages.next_url().is_none());
+    assert_eq!(pages.finish().unwrap(), json!([1, 2, 3]));
+}
+
+#[test]
+fn test_paginate_cursor_and_link() {
+    let (program, request, paginate) = paginated(
+        "PAGINATE CURSOR cursor FROM body.next MAX PAGES 5",
+        "  FROM body.items\n  SELECT id",
+    );
+    let mut pages = Paginator::new(&request, &paginate, page_plan(&program, &request));
+    assert_eq!(pages.next_url(), Some("https://a.test/items?size=2"));
+    pages
+        .add_page(json!({ "items": [1], "next": "a b/c" }), &[])
+        .unwrap();
+    assert_eq!(
+        pages.next_url(),
+        Some("https://a.test/items?size=2&cursor=a%20b%2Fc")
+    );
+    pages
+        .add_page(json!({ "items": [2], "next": null }), &[])
+        .unwrap();
+    assert!(pages.next_url().is_none());
+    assert_eq!(pages.finish().unwrap()["items"], json!([1, 2]));
+
+    let (program, request, paginate) =
+        paginated("PAGINATE LINK MAX PAGES 5", "  FROM body\n  SELECT id");
+    let mut pages = Paginator::new(&request, &paginate, page_plan(&program, &request));
+    let link = |value: &str| vec![("link".to_string(), value.to_string())];
+    pages
+        .add_page(
+            json!([1]),
+            &link(r#"</items?page=1>; rel="prev", </items?page=3>; rel="next""#),
+        )
+        .unwrap();
+    assert_eq!(pages.next_url(), Some("https://a.test/items?page=3"));
+    pages
+        .add_page(
+            json!([2]),
+            &link(r#"<https://a.test/items?page=1>; rel="first""#),
+        )
+        .unwrap();
+    assert!(pages.next_url().is_none());
+}
+
+#[test]
+fn test_substr_before_the_first_character() {
+    let result = run(
+        r#"
+RESPONSE
+  FROM body
+  SELECT {
+    zero: substr(s, 0, 2),
+    negative: substr(s, -1, 3),
+    gone: substr(s, -2, 3),
+    rest: substr(s, -5)
+  }
+"#,
+        &json!([{ "s": "abc" }]),
+    );
+
+    assert_eq!(
+        result,
+        json!([{ "zero": "a", "negative": "a", "gone": "", "rest": "abc" }])
+    );
+}
@@ -0,0 +1,138 @@
+use core_lib::*;
+
+// The headers `sign_request` leaves on the only request in `source`, signed
+// at `now` for the request's own URL
+fn signed(
+    source: &str,
+    now: u64,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>, SignError> {
+    let mut request = parse_program(source).unwrap().request_blocks.remove(0);
+    let url = request.full_url();
+    sign_request(&mut request, &url, now, env)?;
+    Ok(request
+        .headers
+        .into_iter()
+        .map(|h| (h.key, h.value))
+        .collect())
+}
+
+fn no_env(_: &str) -> Option<String> {
+    None
+}
+
+#[test]
+fn test_hmac_sha256_rfc4231() {
+    // RFC 4231 test case 2
+    assert_eq!(
+        hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn test_hmac_signs_template() {
+    let source = r#"
+USING
+  secret: s3cr3t
+REQUEST A
+  POST https://api.test/orders?page=2
+  BODY JSON {"id": 1}
+  SIGN HMAC-SHA256 {secret} HEADER X-Sig OVER "{method} {path}?{query} {body}"
+"#;
+    let expected = hmac_sha256_hex(b"s3cr3t", br#"POST /orders?page=2 {"id": 1}"#);
+    assert_eq!(
+        signed(source, 0, no_env).unwrap(),
+        [("X-Sig".to_string(), expected)]
+    );
+}
+
+#[test]
+fn test_hmac_body_sha256() {
+    let source = "REQUEST A\n  POST https://api.test\n  BODY RAW abc\n  SIGN HMAC-SHA256 k OVER \"{body_sha256}\"";
+    // SHA-256("abc") from FIPS 180-2
+    let expected = hmac_sha256_hex(
+        b"k",
+        b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    );
+    assert_eq!(signed(source, 0, no_env).unwrap()[0].1, expected);
+}
+
+#[test]
+fn test_hmac_sends_signed_timestamp() {
+    // The default template covers the timestamp
+    let source = "REQUEST A\n  GET https://api.test/a\n  SIGN HMAC-SHA256 k";
+    let expected = hmac_sha256_hex(b"k", b"GET\n/a\n1700000000\n");
+    assert_eq!(
+        signed(source, 1_700_000_000, no_env).unwrap(),
+        [
+            ("X-Signature".to_string(), expected),
+            ("X-Timestamp".to_string(), "1700000000".to_string())
+        ]
+    );
+
+    let source = "REQUEST A\n  GET https://api.test/a\n  SIGN HMAC-SHA256 k TIMESTAMP X-Sent-At";
+    let headers = signed(source, 1_700_000_000, no_env).unwrap();
+    assert_eq!(
+        headers[1],
+        ("X-Sent-At".to_string(), "1700000000".to_string())
+    );
+
+    // Nothing to send when the template leaves it out
+    let source = "REQUEST A\n  GET https://api.test/a\n  SIGN HMAC-SHA256 k OVER \"{path}\"";
+    assert_eq!(signed(source, 1_700_000_000, no_env).unwrap().len(), 1);
+}
+
+#[test]
+fn test_aws_sigv4_example() {
+    // The AWS Signature Version 4 example request, at 2015-08-30T12:36:00Z
+    let source = r#"
+REQUEST ListUsers
+  GET https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08
+  HEADER Content-Type: application/x-www-form-urlencoded; charset=utf-8
+  SIGN AWS_SIGV4 iam us-east-1 KEY AKIDEXAMPLE SECRET wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY
+"#;
+    let headers = signed(source, 1_440_938_160, no_env).unwrap();
+    assert!(headers.contains(&("X-Amz-Date".to_string(), "20150830T123600Z".to_string())));
+    assert!(
+        headers.contains(&(
+            "Authorization".to_string(),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+         SignedHeaders=content-type;host;x-amz-date, \
+         Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+                .to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_aws_sigv4_credentials_from_env() {
+    let source =
+        "REQUEST A\n  GET https://s3.amazonaws.com/bucket/key\n  SIGN AWS_SIGV4 s3 eu-west-1";
+    assert!(matches!(
+        signed(source, 0, no_env),
+        Err(SignError::MissingCredentials)
+    ));
+
+    let env = |name: &str| match name {
+        "AWS_ACCESS_KEY_ID" => Some("AKID".to_string()),
+        "AWS_SECRET_ACCESS_KEY" => Some("secret".to_string()),
+        "AWS_SESSION_TOKEN" => Some("session".to_string()),
+        _ => None,
+    };
+    let headers = signed(source, 0, env).unwrap();
+    let names: Vec<&str> = headers.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "X-Amz-Date",
+            "X-Amz-Content-Sha256",
+            "X-Amz-Security-Token",
+            "Authorization"
+        ]
+    );
+    assert!(headers[3].1.starts_with(
+        "AWS4-HMAC-SHA256 Credential=AKID/19700101/eu-west-1/s3/aws4_request, \
+         SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token,"
+    ));
+}
@@ -4,6 +4,7 @@ use runtime::exec::ExecutionRuntime;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
@@ -70,8 +71,75 @@ impl LanguageServer for Backend {
 
         // Add Keywords
         let keywords = vec![
-            "USING", "REQUEST", "RESPONSE", "GET", "POST", "PUT", "DELETE", "PATCH", "AND", "OR",
-            "SELECT", "FROM", "WHERE", "LIMIT",
+            "USING",
+            "REQUEST",
+            "RESPONSE",
+            "GET",
+            "POST",
+            "PUT",
+            "DELETE",
+            "PATCH",
+            "AND",
+            "OR",
+            "NOT",
+            "IN",
+            "BETWEEN",
+            "LIKE",
+            "ILIKE",
+            "IS",
+            "NULL",
+            "CASE",
+            "WHEN",
+            "THEN",
+            "ELSE",
+            "END",
+            "SELECT",
+            "DISTINCT",
+            "FROM",
+            "AS",
+            "JOIN",
+            "LEFT",
+            "INNER",
+            "ON",
+            "UNNEST",
+            "OUTER",
+            "WHERE",
+            "LIMIT",
+            "OFFSET",
+            "FOR",
+            "EACH",
+            "PAGINATE",
+            "PAGE",
+            "CURSOR",
+            "LINK",
+            "MAX",
+            "PAGES",
+            "ITEMS",
+            "BODY",
+            "JSON",
+            "FORM",
+            "RAW",
+            "QUERY",
+            "EXPECT",
+            "STATUS",
+            "RETURN",
+            "RETRY",
+            "BACKOFF",
+            "TIMEOUT",
+            "AUTH",
+            "BASIC",
+            "BEARER",
+            "APIKEY",
+            "OAUTH2",
+            "CLIENT_CREDENTIALS",
+            "SIGN",
+            "HMAC-SHA256",
+            "AWS_SIGV4",
+            "OVER",
+            "KEY",
+            "SECRET",
+            "TOKEN",
+            "TIMESTAMP",
         ];
         for kw in keywords {
             items.push(CompletionItem {
@@ -162,22 +230,20 @@ impl Backend {
             Ok(program) => {
                 // Try to fetch data
                 if let Some(req) = program.request_blocks.first() {
-                    let mut runtime = ExecutionRuntime::new();
-                    match runtime.fetch_data(req) {
+                    // Validation runs on every keystroke, so a slow endpoint must not stall it
+                    let mut runtime = ExecutionRuntime::new()
+                        .with_connect_timeout(Duration::from_secs(2))
+                        .with_deadline(Duration::from_secs(5));
+                    // Chained and FOR EACH requests need the requests they read from
+                    match runtime.fetch_request(&program, &req.name) {
                         Ok(json) => {
                             self.last_response.lock().unwrap().insert(uri.clone(), json);
                         }
                         Err(e) => {
                             let diag = Diagnostic {
                                 range: Range {
-                                    start: Position {
-                                        line: 0,
-                                        character: 0,
-                                    },
-                                    end: Position {
-                                        line: 0,
-                                        character: 0,
-                                    },
+                                    start: offset_to_position(text, req.span.start),
+                                    end: offset_to_position(text, req.span.end),
                                 },
                                 severity: Some(DiagnosticSeverity::WARNING),
                                 message: format!("Failed to fetch data: {}", e),
@@ -189,22 +255,11 @@ impl Backend {
                 }
             }
             Err(e) => {
-                if let ParseError::Syntax {
-                    line,
-                    column,
-                    message,
-                } = e
-                {
+                if let ParseError::Syntax { span, message } = e {
                     let diag = Diagnostic {
                         range: Range {
-                            start: Position {
-                                line: (line as u32).saturating_sub(1),
-                                character: (column as u32).saturating_sub(1),
-                            },
-                            end: Position {
-                                line: (line as u32).saturating_sub(1),
-                                character: (column as u32),
-                            },
+                            start: offset_to_position(text, span.start),
+                            end: offset_to_position(text, span.end.max(span.start + 1)),
                         },
                         severity: Some(DiagnosticSeverity::ERROR),
                         message: format!("{}", message),
@@ -222,6 +277,25 @@ impl Backend {
     }
 }
 
+// Converts a byte offset into an LSP position (UTF-16 columns)
+fn offset_to_position(text: &str, offset: usize) -> Position {
+    let offset = offset.min(text.len());
+    let mut line = 0u32;
+    let mut character = 0u32;
+    for (i, c) in text.char_indices() {
+        if i >= offset {
+            break;
+        }
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16() as u32;
+        }
+    }
+    Position { line, character }
+}
+
 #[tokio::main]
 async fn main() {
     let stdin = tokio::io::stdin();
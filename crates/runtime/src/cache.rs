@@ -6,6 +6,8 @@ use std::time::{Duration, Instant};
 pub struct CacheEntry {
     pub value: Json,
     pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub elapsed: Duration,
     pub timestamp: Instant,
     pub ttl: Duration,
 }
@@ -6,10 +6,24 @@ pub enum RuntimeError {
     HttpRequestError(#[from] reqwest::Error),
     #[error("JSON parsing error: {0}")]
     JsonParseError(#[from] serde_json::Error),
+    #[error("unexpected HTTP status {status}: {body}")]
+    HttpStatus { status: u16, body: String },
+    #[error("authentication failed: {0}")]
+    Auth(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    #[error("signing failed: {0}")]
+    Sign(#[from] core_lib::SignError),
     #[error("Cache error: {0}")]
     CacheError(String),
     #[error("Parse error: {0}")]
     Parse(#[from] core_lib::ParseError),
     #[error("query error: {0}")]
     Query(#[from] core_lib::QueryError),
+    #[error("program has no {0} block")]
+    MissingBlock(&'static str),
+    #[error("unknown request: {0}")]
+    UnknownRequest(String),
+    #[error("requests depend on each other: {0}")]
+    DependencyCycle(String),
 }
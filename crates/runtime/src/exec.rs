@@ -3,12 +3,26 @@ use std::time::{Duration, Instant};
 
 use crate::cache::{Cache, CacheEntry};
 use crate::errors::RuntimeError;
-use crate::http::HttpRuntime;
+use crate::http::{HttpResponse, HttpRuntime};
 use core_lib::*;
 
 pub struct ExecutionRuntime {
     http: HttpRuntime,
     cache: Cache,
+    // Override the OFFSET/LIMIT of every response query, e.g. from the CLI
+    offset: Option<u32>,
+    limit: Option<u32>,
+    // How many FOR EACH item requests may be in flight at once
+    concurrency: usize,
+    // Time budget for a whole run_program, and when the current run must end
+    deadline: Option<Duration>,
+    ends_at: Option<Instant>,
+}
+
+impl Default for ExecutionRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ExecutionRuntime {
@@ -16,84 +30,294 @@ impl ExecutionRuntime {
         Self {
             http: HttpRuntime::new(),
             cache: Cache::default(),
+            offset: None,
+            limit: None,
+            concurrency: 4,
+            deadline: None,
+            ends_at: None,
         }
     }
 
+    pub fn with_offset(mut self, offset: u32) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.http = self.http.with_connect_timeout(timeout);
+        self
+    }
+
+    // Limits each request from connecting to the end of its body; a request's
+    // own TIMEOUT takes precedence
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.http = self.http.with_read_timeout(timeout);
+        self
+    }
+
+    // Fails a run_program or fetch_data call that takes longer than `deadline`
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // Logs every HTTP attempt, including retries, to stderr
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.http = self.http.with_verbose(verbose);
+        self
+    }
+
+    // Runs `signer` on every outgoing request after any SIGN clause, e.g. to
+    // add a signature scheme the language has no clause for
+    pub fn with_signer(
+        mut self,
+        signer: impl Fn(&mut RequestBlock, &str) -> Result<(), SignError> + Send + Sync + 'static,
+    ) -> Self {
+        self.http = self.http.with_signer(signer);
+        self
+    }
+
     pub fn run_source(&mut self, source: &str) -> Result<Json, RuntimeError> {
-        let program = parse_program(source).map_err(|e| RuntimeError::Parse(e))?;
-        let req = program
-            .request_blocks
-            .first()
-            .expect("You must have at least one Request Block");
-        let resp = program
-            .response_blocks
-            .first()
-            .expect("You must have at least one Response Block");
+        let program = parse_program(source).map_err(RuntimeError::Parse)?;
+        self.run_program(&program)
+    }
 
-        self.run_request(req, resp)
+    // Runs every RESPONSE against its request; results are keyed by request name
+    pub fn run_program(&mut self, program: &Program) -> Result<Json, RuntimeError> {
+        let program = &self.with_overrides(program);
+        if program.request_blocks.is_empty() {
+            return Err(RuntimeError::MissingBlock("REQUEST"));
+        }
+        if program.response_blocks.is_empty() {
+            return Err(RuntimeError::MissingBlock("RESPONSE"));
+        }
+
+        self.ends_at = self.deadline.map(|d| Instant::now() + d);
+
+        // Every request runs once, after the requests its URL and headers read from
+        let order = program
+            .request_order()
+            .map_err(|chain| RuntimeError::DependencyCycle(chain.join(" -> ")))?;
+        let sources = self.fetch_in_order(program, order)?;
+
+        let mut results = serde_json::Map::new();
+        for resp in &program.response_blocks {
+            let req = program.request_for(resp).ok_or_else(|| {
+                RuntimeError::UnknownRequest(
+                    resp.request
+                        .as_ref()
+                        .map(|r| r.name.clone())
+                        .unwrap_or_default(),
+                )
+            })?;
+            let result = execute_query_with_sources(&resp.query, &sources[&req.name], &sources)?;
+            results.insert(req.name.clone(), result);
+        }
+        Ok(Json::Object(results))
     }
 
     pub fn fetch_data(&mut self, req: &RequestBlock) -> Result<Json, RuntimeError> {
-        let cache_key = format!("{}:{}", Self::method_to_string(&req.method), req.url);
-        if let Some(body) = self.try_cache(req, &cache_key)? {
-            Ok(body.0)
-        } else {
-            let headers: Vec<(&str, &str)> = req
-                .headers
-                .iter()
-                .map(|h| (h.key.as_str(), h.value.as_str()))
-                .collect();
-
-            let (json, status) = self.http.get_json(&req.url, &headers)?;
-            self.store_cache(req, cache_key, json.clone(), status);
-            Ok(json)
+        self.ends_at = self.deadline.map(|d| Instant::now() + d);
+        Ok(self.fetch(req)?.body)
+    }
+
+    // Fetches the request named `name` and the requests it reads from, and
+    // returns its body
+    pub fn fetch_request(&mut self, program: &Program, name: &str) -> Result<Json, RuntimeError> {
+        self.ends_at = self.deadline.map(|d| Instant::now() + d);
+        let order = program
+            .request_order_for(name)
+            .map_err(|chain| RuntimeError::DependencyCycle(chain.join(" -> ")))?;
+        let mut sources = self.fetch_in_order(program, order)?;
+        sources
+            .remove(name)
+            .map(|mut envelope| envelope["body"].take())
+            .ok_or_else(|| RuntimeError::UnknownRequest(name.to_string()))
+    }
+
+    // Sends each request with the responses of those before it filled in
+    fn fetch_in_order(
+        &mut self,
+        program: &Program,
+        order: Vec<&RequestBlock>,
+    ) -> Result<Sources, RuntimeError> {
+        let mut sources = Sources::new();
+        for req in order {
+            let req = interpolate_request(req, &sources)?;
+            let response = match (&req.for_each, &req.paginate) {
+                (Some(for_each), _) => self.fetch_each(&req, for_each, &sources)?,
+                (None, Some(paginate)) => {
+                    self.fetch_pages(&req, paginate, page_plan(program, &req))?
+                }
+                (None, None) => self.fetch(&req)?,
+            };
+            let envelope = response_envelope(
+                &req.full_url(),
+                response.status,
+                &response.headers,
+                response.body,
+                response.elapsed.as_millis() as u64,
+            );
+            sources.insert(req.name.clone(), envelope);
+        }
+        Ok(sources)
+    }
+
+    fn fetch(&mut self, req: &RequestBlock) -> Result<HttpResponse, RuntimeError> {
+        let cache_key = Self::cache_key(req);
+        if let Some(response) = self.try_cache(req, &cache_key)? {
+            return Ok(response);
+        }
+        let response = self.http.send(req, &req.full_url(), self.ends_at)?;
+        self.store_cache(req, cache_key, &response);
+        Ok(response)
+    }
+
+    // Sends a FOR EACH request once per item and collects the bodies in item
+    // order. Cache misses are fetched `concurrency` at a time. The combined
+    // response carries the highest status seen and the total time taken.
+    fn fetch_each(
+        &mut self,
+        req: &RequestBlock,
+        for_each: &ForEach,
+        sources: &Sources,
+    ) -> Result<HttpResponse, RuntimeError> {
+        let started = Instant::now();
+        let requests = for_each_items(for_each, sources)?
+            .iter()
+            .map(|item| interpolate_item(req, &for_each.alias, item))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut responses = Vec::with_capacity(requests.len());
+        for item in &requests {
+            responses.push(self.try_cache(item, &Self::cache_key(item))?);
         }
+
+        let misses: Vec<usize> = (0..requests.len())
+            .filter(|&i| responses[i].is_none())
+            .collect();
+        for batch in misses.chunks(self.concurrency) {
+            let http = &self.http;
+            let ends_at = self.ends_at;
+            let fetched: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|&i| {
+                        let item = &requests[i];
+                        scope.spawn(move || http.send(item, &item.full_url(), ends_at))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("request thread panicked"))
+                    .collect()
+            });
+
+            for (&i, result) in batch.iter().zip(fetched) {
+                let response = result?;
+                self.store_cache(&requests[i], Self::cache_key(&requests[i]), &response);
+                responses[i] = Some(response);
+            }
+        }
+
+        let responses: Vec<HttpResponse> = responses.into_iter().flatten().collect();
+        Ok(HttpResponse {
+            status: responses.iter().map(|r| r.status).max().unwrap_or(200),
+            headers: Vec::new(),
+            body: Json::Array(responses.into_iter().map(|r| r.body).collect()),
+            elapsed: started.elapsed(),
+        })
     }
 
-    fn run_request(
+    // Follows a PAGINATE clause until a guard or the end of the data is reached.
+    // The merged body is cached under the request's own URL, along with the
+    // status and headers of the last page.
+    fn fetch_pages(
         &mut self,
         req: &RequestBlock,
-        resp: &ResponseBlock,
-    ) -> Result<Json, RuntimeError> {
-        let cache_key = format!("{}:{}", Self::method_to_string(&req.method), req.url);
-        let (body_json, _status) = if let Some(body) = self.try_cache(req, &cache_key)? {
-            body
-        } else {
-            let headers: Vec<(&str, &str)> = req
-                .headers
-                .iter()
-                .map(|h| (h.key.as_str(), h.value.as_str()))
-                .collect();
-
-            let (json, status) = self.http.get_json(&req.url, &headers)?;
-            self.store_cache(req, cache_key, json.clone(), status);
-            (json, status)
+        paginate: &Paginate,
+        plan: PagePlan,
+    ) -> Result<HttpResponse, RuntimeError> {
+        let cache_key = Self::cache_key(req);
+        if let Some(response) = self.try_cache(req, &cache_key)? {
+            return Ok(response);
+        }
+
+        let started = Instant::now();
+        let mut pages = Paginator::new(req, paginate, plan);
+        let mut last = None;
+        while let Some(url) = pages.next_url().map(str::to_string) {
+            let response = self.http.send(req, &url, self.ends_at)?;
+            pages.add_page(response.body, &response.headers)?;
+            last = Some((response.status, response.headers));
+        }
+
+        let (status, headers) = last.unwrap_or_default();
+        let response = HttpResponse {
+            body: pages.finish()?,
+            status,
+            headers,
+            elapsed: started.elapsed(),
         };
+        self.store_cache(req, cache_key, &response);
+        Ok(response)
+    }
+
+    // Requests with a body are told apart by its payload
+    fn cache_key(req: &RequestBlock) -> String {
+        let key = format!("{}:{}", Self::method_to_string(&req.method), req.full_url());
+        match &req.body {
+            Some(body) => format!("{}:{}", key, body.payload()),
+            None => key,
+        }
+    }
 
-        // TODO: Handle status codes appropriately
-        let result = execute_query(&resp.query, &body_json)?;
-        Ok(result)
+    // Applies the OFFSET/LIMIT overrides to every response query
+    fn with_overrides(&self, program: &Program) -> Program {
+        let mut program = program.clone();
+        for resp in program.response_blocks.iter_mut() {
+            resp.query.offset = self.offset.or(resp.query.offset);
+            resp.query.limit = self.limit.or(resp.query.limit);
+        }
+        program
     }
 
     fn try_cache(
         &self,
         _req: &RequestBlock,
         key: &str,
-    ) -> Result<Option<(Json, u16)>, RuntimeError> {
+    ) -> Result<Option<HttpResponse>, RuntimeError> {
         if let Some(entry) = self.cache.get(key) {
-            return Ok(Some((entry.value.clone(), entry.status_code)));
+            return Ok(Some(HttpResponse {
+                body: entry.value.clone(),
+                status: entry.status_code,
+                headers: entry.headers.clone(),
+                elapsed: entry.elapsed,
+            }));
         }
         Ok(None)
     }
 
-    fn store_cache(&mut self, req: &RequestBlock, key: String, body: Json, status: u16) {
+    fn store_cache(&mut self, req: &RequestBlock, key: String, response: &HttpResponse) {
         let entry = CacheEntry {
-            status_code: status,
-            value: body.clone(),
+            status_code: response.status,
+            value: response.body.clone(),
+            headers: response.headers.clone(),
+            elapsed: response.elapsed,
             timestamp: Instant::now(),
             ttl: match req.cache {
                 CacheDuration::None => Duration::from_secs(0),
-                CacheDuration::DurationInSeconds(secs) => Duration::from_secs(secs as u64),
+                CacheDuration::DurationInSeconds(secs) => Duration::from_secs(secs),
             },
         };
         self.cache.insert(key, entry);
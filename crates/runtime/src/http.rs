@@ -1,31 +1,381 @@
+use core_lib::{
+    Auth, AuthScheme, HttpMethods, RequestBlock, SignError, StatusPolicy, body_snippet,
+    client_credentials_authorization, client_credentials_form, parse_retry_after,
+    parse_token_response, sign_request,
+};
+use reqwest::Method;
 use reqwest::blocking::Client;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 use serde_json::Value as Json;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::errors::RuntimeError;
+
+#[derive(Clone)]
+pub struct HttpResponse {
+    pub body: Json,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub elapsed: Duration,
+}
 
 pub struct HttpRuntime {
     client: Client,
+    // OAUTH2 access tokens by token URL, client id and scope, with their expiry
+    tokens: Mutex<HashMap<String, (String, Option<Instant>)>>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    // Log every attempt to stderr
+    verbose: bool,
+    signer: Option<Box<Signer>>,
+}
+
+// Runs on every attempt after SIGN, with the request and the URL it goes to
+pub type Signer = dyn Fn(&mut RequestBlock, &str) -> Result<(), SignError> + Send + Sync;
+
+const TOKEN_EXPIRY_MARGIN_SECS: u64 = 30;
+
+// Until the flags say otherwise, a dead host or a stalled response fails
+// rather than hanging the run
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A response before EXPECT STATUS and ON STATUS are applied
+struct RawResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    text: String,
+}
+
+fn build_client(connect_timeout: Duration, read_timeout: Duration) -> Client {
+    Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(read_timeout)
+        .build()
+        .expect("failed to build HTTP client")
+}
+
+impl Default for HttpRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HttpRuntime {
     pub fn new() -> Self {
         Self {
-            client: Client::new(),
+            client: build_client(DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT),
+            tokens: Mutex::new(HashMap::new()),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            verbose: false,
+            signer: None,
+        }
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self.client = build_client(self.connect_timeout, self.read_timeout);
+        self
+    }
+
+    // Limits each request from connecting to the end of its body, unless the
+    // request sets its own TIMEOUT
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self.client = build_client(self.connect_timeout, self.read_timeout);
+        self
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    // Installs a hook that can add or rewrite headers just before each send
+    pub fn with_signer(
+        mut self,
+        signer: impl Fn(&mut RequestBlock, &str) -> Result<(), SignError> + Send + Sync + 'static,
+    ) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    // Sends `req` to `url` with its declared method, headers and body, retrying
+    // as its RETRY clause allows. An empty response body, as from a 204, reads
+    // as null. A status the request does not expect is an error unless an ON
+    // STATUS branch supplies the body. No attempt or wait runs past `deadline`.
+    pub fn send(
+        &self,
+        req: &RequestBlock,
+        url: &str,
+        deadline: Option<Instant>,
+    ) -> Result<HttpResponse, RuntimeError> {
+        let started = Instant::now();
+        let access_token = self.access_token(req, deadline)?;
+        let (authorized, authorized_url) = req.authorized(url, access_token.as_deref());
+        let attempts = req.retry.as_ref().map_or(1, |r| r.retries + 1);
+        let mut attempt = 1;
+        let raw = loop {
+            let attempt_started = Instant::now();
+            let remaining = deadline.map(|d| d.saturating_duration_since(attempt_started));
+            if remaining.is_some_and(|r| r.is_zero()) {
+                return Err(RuntimeError::Timeout(
+                    "program deadline reached".to_string(),
+                ));
+            }
+            let timeout = match (req.timeout_ms.map(Duration::from_millis), remaining) {
+                (Some(t), Some(r)) => Some(t.min(r)),
+                (t, r) => t.or(r),
+            };
+
+            // Signed per attempt so a retry carries a fresh timestamp
+            let mut signed = authorized.clone();
+            sign_request(&mut signed, &authorized_url, unix_ms() / 1000, |name| {
+                std::env::var(name).ok()
+            })?;
+            if let Some(signer) = &self.signer {
+                signer(&mut signed, &authorized_url)?;
+            }
+
+            let result = self.attempt(&signed, &authorized_url, timeout);
+            let past_deadline = deadline.is_some_and(|d| Instant::now() >= d);
+            let retry = req
+                .retry
+                .as_ref()
+                .filter(|_| attempt < attempts && !past_deadline);
+            let delay = match (&result, retry) {
+                (Ok(raw), Some(retry)) if retry.retries_status(raw.status) => {
+                    let retry_after = raw
+                        .headers
+                        .iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case("retry-after"))
+                        .and_then(|(_, value)| parse_retry_after(value, unix_ms()));
+                    Some(retry.delay_ms(attempt, retry_after, jitter()))
+                }
+                (Err(e), Some(retry)) if e.is_connect() || e.is_timeout() => {
+                    Some(retry.delay_ms(attempt, None, jitter()))
+                }
+                _ => None,
+            };
+
+            if self.verbose {
+                let outcome = match &result {
+                    Ok(raw) => raw.status.to_string(),
+                    Err(e) => format!("error: {}", e),
+                };
+                let next = match delay {
+                    Some(ms) => format!(", retrying in {}ms", ms),
+                    None => String::new(),
+                };
+                eprintln!(
+                    "{} {} -> {} in {}ms (attempt {}/{}){}",
+                    method(req),
+                    url,
+                    outcome,
+                    attempt_started.elapsed().as_millis(),
+                    attempt,
+                    attempts,
+                    next
+                );
+            }
+
+            match delay {
+                Some(ms) => {
+                    let delay = Duration::from_millis(ms);
+                    if deadline.is_some_and(|d| Instant::now() + delay >= d) {
+                        return Err(RuntimeError::Timeout(
+                            "program deadline reached before the next retry".to_string(),
+                        ));
+                    }
+                    thread::sleep(delay);
+                }
+                None => {
+                    break result.map_err(|e| {
+                        if !e.is_timeout() {
+                            e.into()
+                        } else if past_deadline {
+                            RuntimeError::Timeout("program deadline reached".to_string())
+                        } else {
+                            RuntimeError::Timeout(format!("{} {}", method(req), url))
+                        }
+                    })?;
+                }
+            }
+            attempt += 1;
+        };
+
+        let body = match req.status_policy(raw.status) {
+            StatusPolicy::Return(value) => value.clone(),
+            StatusPolicy::Reject => {
+                return Err(RuntimeError::HttpStatus {
+                    status: raw.status,
+                    body: body_snippet(&raw.text),
+                });
+            }
+            StatusPolicy::Accept if raw.text.trim().is_empty() => Json::Null,
+            StatusPolicy::Accept => serde_json::from_str(&raw.text)?,
+        };
+
+        Ok(HttpResponse {
+            body,
+            status: raw.status,
+            headers: raw.headers,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    // The access token for an OAUTH2 request, fetched from its token endpoint
+    // and reused until shortly before it expires. The cache stays locked while
+    // fetching, so FOR EACH items share a single token request.
+    fn access_token(
+        &self,
+        req: &RequestBlock,
+        deadline: Option<Instant>,
+    ) -> Result<Option<String>, RuntimeError> {
+        let Some(Auth {
+            scheme:
+                AuthScheme::OAuth2 {
+                    token_url,
+                    client_id,
+                    secret,
+                    scope,
+                },
+            ..
+        }) = &req.auth
+        else {
+            return Ok(None);
+        };
+
+        let key = format!(
+            "{} {} {}",
+            token_url,
+            client_id,
+            scope.as_deref().unwrap_or("")
+        );
+        let mut tokens = self.tokens.lock().expect("token cache poisoned");
+        if let Some((token, expires)) = tokens.get(&key)
+            && expires.is_none_or(|e| Instant::now() < e)
+        {
+            return Ok(Some(token.clone()));
+        }
+
+        let mut builder = self
+            .client
+            .post(token_url)
+            .header(
+                AUTHORIZATION,
+                client_credentials_authorization(client_id, secret),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(client_credentials_form(scope.as_deref()));
+        if let Some(deadline) = deadline {
+            builder = builder.timeout(deadline.saturating_duration_since(Instant::now()));
         }
+        // Timing out here is reported like timing out on the request itself
+        let timed_out = |e: reqwest::Error| {
+            if !e.is_timeout() {
+                e.into()
+            } else if deadline.is_some_and(|d| Instant::now() >= d) {
+                RuntimeError::Timeout("program deadline reached".to_string())
+            } else {
+                RuntimeError::Timeout(format!("POST {} (OAuth2 token)", token_url))
+            }
+        };
+        let resp = builder.send().map_err(timed_out)?;
+        let status = resp.status().as_u16();
+        let text = resp.text().map_err(timed_out)?;
+        if self.verbose {
+            eprintln!("POST {} -> {} (OAuth2 token)", token_url, status);
+        }
+        if !(200..300).contains(&status) {
+            return Err(RuntimeError::Auth(format!(
+                "token request to {} failed with status {}: {}",
+                token_url,
+                status,
+                body_snippet(&text)
+            )));
+        }
+
+        let (token, expires_in) = serde_json::from_str(&text)
+            .ok()
+            .and_then(|body| parse_token_response(&body))
+            .ok_or_else(|| {
+                RuntimeError::Auth(format!(
+                    "no access_token in the response from {}",
+                    token_url
+                ))
+            })?;
+        // Renew a little early so a token does not expire in flight
+        let expires = expires_in.map(|secs| {
+            Instant::now() + Duration::from_secs(secs.saturating_sub(TOKEN_EXPIRY_MARGIN_SECS))
+        });
+        tokens.insert(key, (token.clone(), expires));
+        Ok(Some(token))
     }
 
-    pub fn get_json(
+    fn attempt(
         &self,
+        req: &RequestBlock,
         url: &str,
-        headers: &[(&str, &str)],
-    ) -> Result<(Json, u16), reqwest::Error> {
-        let mut req = self.client.get(url);
-        for (key, value) in headers {
-            req = req.header(*key, *value);
+        timeout: Option<Duration>,
+    ) -> Result<RawResponse, reqwest::Error> {
+        let mut builder = self.client.request(method(req), url);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        for header in &req.headers {
+            builder = builder.header(&header.key, &header.value);
+        }
+        if let Some(body) = &req.body {
+            let has_content_type = req
+                .headers
+                .iter()
+                .any(|h| h.key.eq_ignore_ascii_case("content-type"));
+            if !has_content_type {
+                builder = builder.header(CONTENT_TYPE, body.content_type());
+            }
+            builder = builder.body(body.payload());
         }
 
-        let resp = req.send()?;
+        let resp = builder.send()?;
         let status = resp.status().as_u16();
-        let json = resp.json::<Json>()?;
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        Ok(RawResponse {
+            status,
+            headers,
+            text: resp.text()?,
+        })
+    }
+}
 
-        Ok((json, status))
+fn method(req: &RequestBlock) -> Method {
+    match req.method {
+        HttpMethods::Get => Method::GET,
+        HttpMethods::Post => Method::POST,
+        HttpMethods::Put => Method::PUT,
+        HttpMethods::Delete => Method::DELETE,
+        HttpMethods::Patch => Method::PATCH,
     }
 }
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// A random number in [0, 1), drawn from the per-process random hasher keys
+fn jitter() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
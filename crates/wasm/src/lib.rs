@@ -1,53 +1,358 @@
 use core_lib::*;
 use serde_json::Value as Json;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub async fn run(source: String) -> Result<JsValue, JsValue> {
     let program = parse_program(&source).map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
 
-    let req = program
-        .request_blocks
-        .first()
-        .ok_or_else(|| JsValue::from_str("You must have at least one Request Block"))?;
-    let resp = program
-        .response_blocks
-        .first()
-        .ok_or_else(|| JsValue::from_str("You must have at least one Response Block"))?;
+    if program.request_blocks.is_empty() {
+        return Err(JsValue::from_str("program has no REQUEST block"));
+    }
+    if program.response_blocks.is_empty() {
+        return Err(JsValue::from_str("program has no RESPONSE block"));
+    }
+
+    // Every request runs once, after the requests its URL and headers read from
+    let order = program.request_order().map_err(|chain| {
+        JsValue::from_str(&format!(
+            "requests depend on each other: {}",
+            chain.join(" -> ")
+        ))
+    })?;
+    let mut sources = Sources::new();
+    for req in order {
+        let req = interpolate_request(req, &sources)
+            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+        let response = match (&req.for_each, &req.paginate) {
+            (Some(for_each), _) => fetch_each(&req, for_each, &sources).await?,
+            (None, Some(paginate)) => {
+                fetch_pages(&req, paginate, page_plan(&program, &req)).await?
+            }
+            (None, None) => fetch_page(&req, &req.full_url()).await?,
+        };
+        let envelope = response_envelope(
+            &req.full_url(),
+            response.status,
+            &response.headers,
+            response.body,
+            response.elapsed_ms as u64,
+        );
+        sources.insert(req.name.clone(), envelope);
+    }
+
+    // Results are keyed by request name, like the native runtime
+    let mut results = serde_json::Map::new();
+    for resp in &program.response_blocks {
+        let req = program.request_for(resp).ok_or_else(|| {
+            let name = resp.request.as_ref().map(|r| r.name.as_str());
+            JsValue::from_str(&format!("unknown request: {}", name.unwrap_or_default()))
+        })?;
+
+        // Execute query
+        let result = execute_query_with_sources(&resp.query, &sources[&req.name], &sources)
+            .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+        results.insert(req.name.clone(), result);
+    }
+
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    use serde::Serialize;
+    Ok(Json::Object(results).serialize(&serializer)?)
+}
+
+// How many FOR EACH item requests may be in flight at once
+const FOR_EACH_CONCURRENCY: usize = 4;
+
+struct Fetched {
+    body: Json,
+    status: u16,
+    headers: Vec<(String, String)>,
+    elapsed_ms: f64,
+}
+
+// The combined response carries the highest status seen and the total time taken
+async fn fetch_each(
+    req: &RequestBlock,
+    for_each: &ForEach,
+    sources: &Sources,
+) -> Result<Fetched, JsValue> {
+    let started = js_sys::Date::now();
+    let to_js = |e: QueryError| JsValue::from_str(&format!("{:?}", e));
+    let requests = for_each_items(for_each, sources)
+        .map_err(to_js)?
+        .iter()
+        .map(|item| interpolate_item(req, &for_each.alias, item).map_err(to_js))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut bodies = Vec::with_capacity(requests.len());
+    let mut status = 200;
+    for batch in requests.chunks(FOR_EACH_CONCURRENCY) {
+        let pages = batch.iter().map(|item| fetch_page(item, item.full_url()));
+        for page in futures::future::join_all(pages).await {
+            let page = page?;
+            status = status.max(page.status);
+            bodies.push(page.body);
+        }
+    }
+    Ok(Fetched {
+        body: Json::Array(bodies),
+        status,
+        headers: Vec::new(),
+        elapsed_ms: js_sys::Date::now() - started,
+    })
+}
+
+// The merged response keeps the status and headers of the last page
+async fn fetch_pages(
+    req: &RequestBlock,
+    paginate: &Paginate,
+    plan: PagePlan,
+) -> Result<Fetched, JsValue> {
+    let started = js_sys::Date::now();
+    let to_js = |e: QueryError| JsValue::from_str(&format!("{:?}", e));
+    let mut pages = Paginator::new(req, paginate, plan);
+    let mut last = None;
+    while let Some(url) = pages.next_url().map(str::to_string) {
+        let page = fetch_page(req, url).await?;
+        pages.add_page(page.body, &page.headers).map_err(to_js)?;
+        last = Some((page.status, page.headers));
+    }
+
+    let (status, headers) = last.unwrap_or_default();
+    Ok(Fetched {
+        body: pages.finish().map_err(to_js)?,
+        status,
+        headers,
+        elapsed_ms: js_sys::Date::now() - started,
+    })
+}
+
+// Sends one request, retrying as its RETRY clause allows; every attempt is
+// logged with console.debug
+async fn fetch_page(req: &RequestBlock, url: impl AsRef<str>) -> Result<Fetched, JsValue> {
+    let url = url.as_ref();
+    let started = js_sys::Date::now();
+    let access_token = access_token(req).await?;
+    let (authorized, authorized_url) = req.authorized(url, access_token.as_deref());
+    let attempts = req.retry.as_ref().map_or(1, |r| r.retries + 1);
+    let mut attempt = 1;
+    let (status, headers, text) = loop {
+        let attempt_started = js_sys::Date::now();
+        // Signed per attempt so a retry carries a fresh timestamp; browsers
+        // have no environment, so AWS_SIGV4 needs KEY and SECRET
+        let mut signed = authorized.clone();
+        sign_request(
+            &mut signed,
+            &authorized_url,
+            (js_sys::Date::now() / 1000.0) as u64,
+            |_| None,
+        )
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let result = send(&signed, &authorized_url).await;
+        let retry = req.retry.as_ref().filter(|_| attempt < attempts);
+        let delay = match (&result, retry) {
+            (Ok((status, headers, _)), Some(retry)) if retry.retries_status(*status) => {
+                let retry_after = headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("retry-after"))
+                    .and_then(|(_, value)| parse_retry_after(value, js_sys::Date::now() as u64));
+                Some(retry.delay_ms(attempt, retry_after, js_sys::Math::random()))
+            }
+            // Fetch does not tell connection errors apart, so any failure is retried
+            (Err(_), Some(retry)) => Some(retry.delay_ms(attempt, None, js_sys::Math::random())),
+            _ => None,
+        };
+
+        let outcome = match &result {
+            Ok((status, _, _)) => status.to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+        let next = match delay {
+            Some(ms) => format!(", retrying in {}ms", ms),
+            None => String::new(),
+        };
+        web_sys::console::debug_1(&JsValue::from_str(&format!(
+            "{} {} -> {} in {}ms (attempt {}/{}){}",
+            method(req),
+            url,
+            outcome,
+            js_sys::Date::now() - attempt_started,
+            attempt,
+            attempts,
+            next
+        )));
 
-    // Execute request
+        match delay {
+            Some(ms) => sleep(ms).await?,
+            None => break result.map_err(|e| JsValue::from_str(&e.to_string()))?,
+        }
+        attempt += 1;
+    };
+
+    // An empty body, as from a 204, reads as null; EXPECT STATUS and ON STATUS
+    // decide what other statuses mean
+    let body = match req.status_policy(status) {
+        StatusPolicy::Return(value) => value.clone(),
+        StatusPolicy::Reject => {
+            return Err(JsValue::from_str(&format!(
+                "unexpected HTTP status {}: {}",
+                status,
+                body_snippet(&text)
+            )));
+        }
+        StatusPolicy::Accept if text.trim().is_empty() => Json::Null,
+        StatusPolicy::Accept => {
+            serde_json::from_str(&text).map_err(|e| JsValue::from_str(&e.to_string()))?
+        }
+    };
+    Ok(Fetched {
+        body,
+        status,
+        headers,
+        elapsed_ms: js_sys::Date::now() - started,
+    })
+}
+
+async fn send(
+    req: &RequestBlock,
+    url: &str,
+) -> Result<(u16, Vec<(String, String)>, String), reqwest::Error> {
     let client = reqwest::Client::new();
-    let mut request_builder = client.request(
-        match req.method {
-            HttpMethods::Get => reqwest::Method::GET,
-            HttpMethods::Post => reqwest::Method::POST,
-            HttpMethods::Put => reqwest::Method::PUT,
-            HttpMethods::Delete => reqwest::Method::DELETE,
-            HttpMethods::Patch => reqwest::Method::PATCH,
-        },
-        &req.url,
-    );
+    let mut request_builder = client.request(method(req), url);
+    if let Some(ms) = req.timeout_ms {
+        request_builder = request_builder.timeout(std::time::Duration::from_millis(ms));
+    }
 
     for header in &req.headers {
         request_builder = request_builder.header(&header.key, &header.value);
     }
+    if let Some(body) = &req.body {
+        let has_content_type = req
+            .headers
+            .iter()
+            .any(|h| h.key.eq_ignore_ascii_case("content-type"));
+        if !has_content_type {
+            request_builder = request_builder.header("Content-Type", body.content_type());
+        }
+        request_builder = request_builder.body(body.payload());
+    }
+
+    let response = request_builder.send().await?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    Ok((status, headers, response.text().await?))
+}
+
+thread_local! {
+    // OAUTH2 access tokens by token URL, client id and scope, with their expiry
+    // in milliseconds since the epoch
+    static TOKENS: RefCell<HashMap<String, (String, Option<f64>)>> = RefCell::new(HashMap::new());
+}
+
+const TOKEN_EXPIRY_MARGIN_SECS: u64 = 30;
+
+// The access token for an OAUTH2 request, fetched from its token endpoint and
+// reused until shortly before it expires
+async fn access_token(req: &RequestBlock) -> Result<Option<String>, JsValue> {
+    let Some(Auth {
+        scheme:
+            AuthScheme::OAuth2 {
+                token_url,
+                client_id,
+                secret,
+                scope,
+            },
+        ..
+    }) = &req.auth
+    else {
+        return Ok(None);
+    };
+
+    let key = format!(
+        "{} {} {}",
+        token_url,
+        client_id,
+        scope.as_deref().unwrap_or("")
+    );
+    let cached = TOKENS.with(|tokens| {
+        tokens
+            .borrow()
+            .get(&key)
+            .filter(|(_, expires)| expires.is_none_or(|e| js_sys::Date::now() < e))
+            .map(|(token, _)| token.clone())
+    });
+    if cached.is_some() {
+        return Ok(cached);
+    }
 
-    let response = request_builder
+    let to_js = |e: reqwest::Error| JsValue::from_str(&e.to_string());
+    let response = reqwest::Client::new()
+        .post(token_url)
+        .header(
+            "Authorization",
+            client_credentials_authorization(client_id, secret),
+        )
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(client_credentials_form(scope.as_deref()))
         .send()
         .await
-        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        .map_err(to_js)?;
+    let status = response.status().as_u16();
+    let text = response.text().await.map_err(to_js)?;
+    web_sys::console::debug_1(&JsValue::from_str(&format!(
+        "POST {} -> {} (OAuth2 token)",
+        token_url, status
+    )));
+    if !(200..300).contains(&status) {
+        return Err(JsValue::from_str(&format!(
+            "authentication failed: token request to {} failed with status {}: {}",
+            token_url,
+            status,
+            body_snippet(&text)
+        )));
+    }
 
-    let _status = response.status().as_u16();
-    let body_json: Json = response
-        .json()
-        .await
-        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let (token, expires_in) = serde_json::from_str(&text)
+        .ok()
+        .and_then(|body| parse_token_response(&body))
+        .ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "authentication failed: no access_token in the response from {}",
+                token_url
+            ))
+        })?;
+    // Renew a little early so a token does not expire in flight
+    let expires = expires_in.map(|secs| {
+        js_sys::Date::now() + secs.saturating_sub(TOKEN_EXPIRY_MARGIN_SECS) as f64 * 1000.0
+    });
+    TOKENS.with(|tokens| tokens.borrow_mut().insert(key, (token.clone(), expires)));
+    Ok(Some(token))
+}
 
-    // Execute query
-    let result = execute_query(&resp.query, &body_json)
-        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+fn method(req: &RequestBlock) -> reqwest::Method {
+    match req.method {
+        HttpMethods::Get => reqwest::Method::GET,
+        HttpMethods::Post => reqwest::Method::POST,
+        HttpMethods::Put => reqwest::Method::PUT,
+        HttpMethods::Delete => reqwest::Method::DELETE,
+        HttpMethods::Patch => reqwest::Method::PATCH,
+    }
+}
 
-    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
-    use serde::Serialize;
-    Ok(result.serialize(&serializer)?)
+// Resolves after `ms` through the host's setTimeout, in browsers and Node alike
+async fn sleep(ms: u64) -> Result<(), JsValue> {
+    let set_timeout: js_sys::Function =
+        js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))?.dyn_into()?;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(ms as f64));
+    });
+    wasm_bindgen_futures::JsFuture::from(promise).await?;
+    Ok(())
 }